@@ -0,0 +1,143 @@
+use crate::error::{Error, Result};
+
+use std::{fmt, str::FromStr};
+
+// Encoded lengths of standard and subaddresses, and of integrated addresses.
+const ADDRESS_LENGTH: usize = 95;
+const INTEGRATED_LENGTH: usize = 106;
+// Prefix byte, public spend and view keys.
+const KEYS_SIZE: usize = 1 + 32 + 32;
+const PAYMENT_ID_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Stagenet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressKind {
+    Standard,
+    /// Standard address with an 8-byte payment ID.
+    Integrated,
+    Subaddress,
+}
+
+/// A Monero wallet address whose prefix, length and checksum are valid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    pub network: Network,
+    pub kind: AddressKind,
+}
+
+impl Address {
+    /// Checks the address in a pool login. Pools take a worker name or a
+    /// fixed difficulty after it, as in `<address>.rig1` or `<address>+50000`.
+    pub fn from_login(login: &str) -> Result<Self> {
+        let address = login.split(['.', '+']).next().unwrap_or(login);
+        address.parse()
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(address: &str) -> Result<Self> {
+        let invalid = |reason: String| {
+            Error::Config(format!("Invalid Monero address {}: {}", address, reason))
+        };
+        if address.len() != ADDRESS_LENGTH && address.len() != INTEGRATED_LENGTH {
+            return Err(invalid(format!(
+                "expected {} or {} characters, got {}",
+                ADDRESS_LENGTH,
+                INTEGRATED_LENGTH,
+                address.len()
+            )));
+        }
+        let bytes = base58_monero::decode_check(address).map_err(|e| invalid(e.to_string()))?;
+        let (network, kind) = match bytes[0] {
+            18 => (Network::Mainnet, AddressKind::Standard),
+            19 => (Network::Mainnet, AddressKind::Integrated),
+            42 => (Network::Mainnet, AddressKind::Subaddress),
+            53 => (Network::Testnet, AddressKind::Standard),
+            54 => (Network::Testnet, AddressKind::Integrated),
+            63 => (Network::Testnet, AddressKind::Subaddress),
+            24 => (Network::Stagenet, AddressKind::Standard),
+            25 => (Network::Stagenet, AddressKind::Integrated),
+            36 => (Network::Stagenet, AddressKind::Subaddress),
+            prefix => return Err(invalid(format!("unknown prefix {}", prefix))),
+        };
+        let size = match kind {
+            AddressKind::Integrated => KEYS_SIZE + PAYMENT_ID_SIZE,
+            _ => KEYS_SIZE,
+        };
+        if bytes.len() != size {
+            return Err(invalid(format!("wrong length for {} address", kind)));
+        }
+        Ok(Self { network, kind })
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Stagenet => "stagenet",
+        })
+    }
+}
+
+impl fmt::Display for AddressKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AddressKind::Standard => "standard",
+            AddressKind::Integrated => "integrated",
+            AddressKind::Subaddress => "subaddress",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STANDARD: &str = "44qARb3o5kWimeStvm9g4r5kTCMSZio8SEWDcEy9HKnnXg6iQns7Mqi4SrrSNZV6mG1YQWqRgr5Lph1BxfQFK8Kz8hMidXR";
+    const SUBADDRESS: &str = "888tNkZrPN6JsEgekjMnABU4TBzc2Dt29EPAvkRxbANsAnjyPbb3iQ1YBRk1UXcdRsiKc9dhwMVgN5S9cQUiyoogDavup3H";
+    const INTEGRATED: &str = "4LL9oSLmtpccfufTMvppY6JwXNouMBzSkbLYfpAV5Usx3skxNgYeYTRj5UzqtReoS44qo9mtmXCqY45DJ852K5Jv2bYXZKKQePHES9khPK";
+
+    #[test]
+    fn test_valid_addresses() {
+        let kind = |address: &str| address.parse::<Address>().unwrap().kind;
+        assert_eq!(kind(STANDARD), AddressKind::Standard);
+        assert_eq!(kind(SUBADDRESS), AddressKind::Subaddress);
+        assert_eq!(kind(INTEGRATED), AddressKind::Integrated);
+        assert_eq!(
+            STANDARD.parse::<Address>().unwrap().network,
+            Network::Mainnet
+        );
+        assert!(Address::from_login(&format!("{}.rig1", STANDARD)).is_ok());
+        assert!(Address::from_login(&format!("{}+50000", SUBADDRESS)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_addresses() {
+        // One character changed breaks the checksum.
+        let typo = STANDARD.replacen("44q", "44r", 1);
+        for address in ["", "x", &STANDARD[..94], &typo, &"1".repeat(95)] {
+            assert!(
+                matches!(address.parse::<Address>(), Err(Error::Config(_))),
+                "{} parsed",
+                address
+            );
+        }
+        // A valid encoding with a Bitcoin-style version byte.
+        let mut bytes = vec![0u8; KEYS_SIZE];
+        bytes[0] = 0x05;
+        let foreign = base58_monero::encode_check(&bytes).unwrap();
+        assert_eq!(foreign.len(), ADDRESS_LENGTH);
+        let err = foreign.parse::<Address>().unwrap_err();
+        assert!(err.to_string().contains("unknown prefix 5"));
+    }
+}
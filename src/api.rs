@@ -0,0 +1,372 @@
+mod metrics;
+
+use crate::{
+    control::{Command, Controller},
+    error::{Error, Result},
+    stats::Stats,
+    stratum::ConnectionState,
+};
+
+use serde::{Deserialize, Serialize};
+use std::{io, net::SocketAddr, sync::Arc, thread};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const ALGO: &str = "rx/0";
+
+/// Summary in the shape of xmrig's `/1/summary`, plus the stale and timed out
+/// share counts and the current job.
+#[derive(Debug, Serialize)]
+struct Summary {
+    id: &'static str,
+    worker_id: String,
+    uptime: u64,
+    restricted: bool,
+    version: &'static str,
+    kind: &'static str,
+    ua: String,
+    algo: &'static str,
+    hashrate: Hashrate,
+    results: Results,
+    connection: Connection,
+    job: Option<JobSummary>,
+}
+
+#[derive(Debug, Serialize)]
+struct Hashrate {
+    /// Over 10 seconds, 60 seconds and 15 minutes.
+    total: [Option<f64>; 3],
+    highest: Option<f64>,
+    threads: Vec<[Option<f64>; 3]>,
+    /// Threads that could not set up a VM and hash nothing.
+    threads_failed: Vec<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct Results {
+    diff_current: u64,
+    shares_good: u64,
+    shares_total: u64,
+    shares_stale: u64,
+    /// Found for a replaced job and dropped instead of submitted.
+    shares_stale_dropped: u64,
+    /// Found but dropped by the local verifier.
+    shares_invalid: u64,
+    hashes_total: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Connection {
+    pool: Option<String>,
+    state: &'static str,
+    uptime: u64,
+    failures: u64,
+    algo: &'static str,
+    diff: u64,
+    accepted: u64,
+    rejected: u64,
+    stale: u64,
+    timeout: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct JobSummary {
+    id: String,
+    diff: u64,
+    seed_hash: String,
+    age: u64,
+}
+
+fn summary(stats: &Stats, restricted: bool) -> Summary {
+    let shares = stats.shares();
+    let job = stats.job();
+    let connection = stats.connection();
+    let diff = job.as_ref().map_or(0, |job| job.difficulty.0);
+    Summary {
+        id: env!("CARGO_PKG_NAME"),
+        worker_id: hostname(),
+        uptime: stats.uptime().as_secs(),
+        restricted,
+        version: env!("CARGO_PKG_VERSION"),
+        kind: "miner",
+        ua: format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        algo: ALGO,
+        hashrate: Hashrate {
+            total: stats.hashrate(),
+            highest: stats.highest_hashrate(),
+            threads: stats.thread_hashrates(),
+            threads_failed: stats.failed_threads(),
+        },
+        results: Results {
+            diff_current: diff,
+            shares_good: shares.accepted,
+            shares_total: shares.total(),
+            shares_stale: shares.stale,
+            shares_stale_dropped: stats.stale_dropped(),
+            shares_invalid: stats.verification().invalid,
+            hashes_total: stats.hashes_total(),
+        },
+        connection: Connection {
+            pool: connection.as_ref().map(|c| c.endpoint.clone()),
+            state: match connection.as_ref().map(|c| c.state) {
+                Some(ConnectionState::Connected) => "connected",
+                Some(ConnectionState::Reconnecting { .. }) => "reconnecting",
+                Some(ConnectionState::Failed) => "failed",
+                None => "connecting",
+            },
+            uptime: connection
+                .as_ref()
+                .filter(|c| c.state == ConnectionState::Connected)
+                .map_or(0, |c| c.since.elapsed().as_secs()),
+            failures: connection.as_ref().map_or(0, |c| c.failures),
+            algo: ALGO,
+            diff,
+            accepted: shares.accepted,
+            rejected: shares.rejected,
+            stale: shares.stale,
+            timeout: shares.timeout,
+        },
+        job: job.map(|job| JobSummary {
+            id: job.id,
+            diff: job.difficulty.0,
+            seed_hash: hex::encode(job.seed),
+            age: job.received.elapsed().as_secs(),
+        }),
+    }
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+/// Serves the JSON API on `addr` in the background and returns the address it
+/// is bound to. With a `token`, requests have to carry it as
+/// `Authorization: Bearer <token>`, and `control` is exposed as xmrig-style
+/// JSON-RPC on `POST /json_rpc`; without one the API is read-only.
+pub fn serve(
+    addr: SocketAddr,
+    token: Option<String>,
+    stats: Arc<Stats>,
+    control: Option<Controller>,
+) -> Result<SocketAddr> {
+    let server = Server::http(addr).map_err(|e| Error::Io(io::Error::other(e)))?;
+    let addr = server.server_addr().to_ip().unwrap_or(addr);
+    tracing::info!("HTTP API listening on http://{}/1/summary", addr);
+    let control = control.filter(|_| token.is_some());
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let response = handle(&mut request, token.as_deref(), &stats, control.as_ref());
+            if let Err(e) = request.respond(response) {
+                tracing::debug!("Failed to answer API request: {}", e);
+            }
+        }
+    });
+    Ok(addr)
+}
+
+fn handle(
+    request: &mut Request,
+    token: Option<&str>,
+    stats: &Stats,
+    control: Option<&Controller>,
+) -> Response<io::Cursor<Vec<u8>>> {
+    if !authorized(request, token) {
+        return json(401, &serde_json::json!({ "error": "unauthorized" }))
+            .with_header(Header::from_bytes("WWW-Authenticate", "Bearer").expect("valid header"));
+    }
+    match (request.method(), request.url()) {
+        (Method::Get, "/1/summary" | "/api.json") => json(200, &summary(stats, control.is_none())),
+        (Method::Get, "/metrics") => Response::from_string(metrics::render(stats)).with_header(
+            Header::from_bytes("Content-Type", "text/plain; version=0.0.4").expect("valid header"),
+        ),
+        (Method::Post, "/json_rpc") => match control {
+            Some(control) => json_rpc(request, control),
+            None => json(403, &serde_json::json!({ "error": "read-only" })),
+        },
+        _ => json(404, &serde_json::json!({ "error": "not found" })),
+    }
+}
+
+// Carries out a JSON-RPC call like `{"method":"set_threads","params":{"threads":4}}`.
+fn json_rpc(request: &mut Request, control: &Controller) -> Response<io::Cursor<Vec<u8>>> {
+    let call: serde_json::Value = match serde_json::from_reader(request.as_reader()) {
+        Ok(call) => call,
+        Err(e) => return rpc_error(400, None, -32700, &e.to_string()),
+    };
+    let id = call.get("id").cloned();
+    let command = match Command::deserialize(&call) {
+        Ok(command) => command,
+        Err(e) => return rpc_error(400, id, -32601, &e.to_string()),
+    };
+    match control.send(command) {
+        Ok(()) => json(
+            200,
+            &serde_json::json!({ "id": id, "jsonrpc": "2.0", "result": { "status": "OK" } }),
+        ),
+        Err(e) => rpc_error(500, id, -32603, &e.to_string()),
+    }
+}
+
+fn rpc_error(
+    status: u16,
+    id: Option<serde_json::Value>,
+    code: i32,
+    message: &str,
+) -> Response<io::Cursor<Vec<u8>>> {
+    json(
+        status,
+        &serde_json::json!({
+            "id": id,
+            "jsonrpc": "2.0",
+            "error": { "code": code, "message": message },
+        }),
+    )
+}
+
+fn authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        .is_some_and(|given| constant_time_eq(given.as_bytes(), token.as_bytes()))
+}
+
+// Takes as long for any two inputs of the same length, so response times do
+// not reveal how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    let diff = a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y));
+    a.len() == b.len() && std::hint::black_box(diff) == 0
+}
+
+fn json<T: Serialize>(status: u16, body: &T) -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_data(serde_json::to_vec(body).unwrap_or_default())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").expect("valid header"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        job::{test_job, Job},
+        share::ShareOutcome,
+    };
+    use std::io::{Read, Write};
+
+    fn send(addr: SocketAddr, request: &str, token: Option<&str>, body: &str) -> String {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        let auth = token.map_or(String::new(), |t| {
+            format!("Authorization: Bearer {}\r\n", t)
+        });
+        write!(
+            stream,
+            "{} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            request,
+            auth,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn get(addr: SocketAddr, token: Option<&str>) -> String {
+        send(addr, "GET /1/summary", token, "")
+    }
+
+    #[test]
+    fn test_summary_endpoint() {
+        let stats = Stats::new(2);
+        stats.record_job(&Job {
+            seed: vec![0xab; 32],
+            ..test_job("job1", 10000)
+        });
+        stats.record_state("pool:3333".into(), ConnectionState::Connected);
+        stats.record_outcome(&ShareOutcome::Accepted);
+        stats.record_outcome(&ShareOutcome::Stale);
+        stats.add_hashes(1, 5);
+
+        let addr = serve(
+            "127.0.0.1:0".parse().unwrap(),
+            Some("secret".into()),
+            stats,
+            None,
+        )
+        .unwrap();
+
+        assert!(get(addr, None).starts_with("HTTP/1.1 401"));
+        assert!(get(addr, Some("wrong")).starts_with("HTTP/1.1 401"));
+        assert!(get(addr, Some("secreT")).starts_with("HTTP/1.1 401"));
+        assert!(get(addr, Some("secrets")).starts_with("HTTP/1.1 401"));
+
+        let response = get(addr, Some("secret"));
+        assert!(response.starts_with("HTTP/1.1 200"));
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        let summary: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(summary["algo"], "rx/0");
+        assert_eq!(summary["hashrate"]["threads"].as_array().unwrap().len(), 2);
+        assert_eq!(summary["results"]["diff_current"], 10000);
+        assert_eq!(summary["results"]["shares_good"], 1);
+        assert_eq!(summary["results"]["shares_total"], 2);
+        assert_eq!(summary["results"]["hashes_total"], 5);
+        assert_eq!(summary["connection"]["pool"], "pool:3333");
+        assert_eq!(summary["connection"]["state"], "connected");
+        assert_eq!(summary["connection"]["stale"], 1);
+        assert_eq!(summary["job"]["id"], "job1");
+        assert_eq!(summary["job"]["seed_hash"], "ab".repeat(32));
+        assert_eq!(summary["restricted"], true);
+    }
+
+    #[test]
+    fn test_control_endpoint() {
+        let (events, event_rx) = crate::event::channel();
+        let addr = serve(
+            "127.0.0.1:0".parse().unwrap(),
+            Some("secret".into()),
+            Stats::new(1),
+            Some(Controller::new(events)),
+        )
+        .unwrap();
+        std::thread::spawn(move || {
+            for event in event_rx {
+                let crate::event::Event::Command(request) = event else {
+                    continue;
+                };
+                let result = match request.command {
+                    Command::SwitchPool { .. } => Err(Error::Config("single pool".into())),
+                    _ => Ok(()),
+                };
+                request.reply.send(result).unwrap();
+            }
+        });
+        let call = |token, body| send(addr, "POST /json_rpc", token, body);
+
+        let pause = r#"{"id":1,"jsonrpc":"2.0","method":"pause"}"#;
+        assert!(call(None, pause).starts_with("HTTP/1.1 401"));
+        let response = call(Some("secret"), pause);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with(r#"{"id":1,"jsonrpc":"2.0","result":{"status":"OK"}}"#));
+        assert!(call(
+            Some("secret"),
+            r#"{"method":"set_threads","params":{"threads":2}}"#
+        )
+        .starts_with("HTTP/1.1 200"));
+        assert!(call(
+            Some("secret"),
+            r#"{"method":"switch_pool","params":{"index":3}}"#
+        )
+        .starts_with("HTTP/1.1 500"));
+        assert!(call(Some("secret"), r#"{"method":"reboot"}"#).starts_with("HTTP/1.1 400"));
+
+        let summary = get(addr, Some("secret"));
+        assert!(summary.contains(r#""restricted":false"#));
+    }
+}
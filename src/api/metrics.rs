@@ -0,0 +1,143 @@
+use crate::{stats::Stats, stratum::ConnectionState};
+
+use std::fmt::Write;
+
+/// Renders `stats` in the Prometheus text exposition format.
+pub fn render(stats: &Stats) -> String {
+    let mut out = String::new();
+    let shares = stats.shares();
+    let connected = stats
+        .connection()
+        .is_some_and(|c| c.state == ConnectionState::Connected);
+
+    metric(
+        &mut out,
+        "orng_hashes_total",
+        "counter",
+        "Hashes computed since startup.",
+        &[("", stats.hashes_total() as f64)],
+    );
+    metric(
+        &mut out,
+        "orng_shares_total",
+        "counter",
+        "Submitted shares by result.",
+        &[
+            ("result=\"accepted\"", shares.accepted as f64),
+            ("result=\"rejected\"", shares.rejected as f64),
+            ("result=\"stale\"", shares.stale as f64),
+            ("result=\"timeout\"", shares.timeout as f64),
+        ],
+    );
+    metric(
+        &mut out,
+        "orng_stale_shares_dropped_total",
+        "counter",
+        "Shares found for a replaced job and dropped instead of submitted.",
+        &[("", stats.stale_dropped() as f64)],
+    );
+    let verified = stats.verification();
+    metric(
+        &mut out,
+        "orng_verified_shares_total",
+        "counter",
+        "Shares recomputed by the local verifier before submitting, by result.",
+        &[
+            (
+                "result=\"valid\"",
+                (verified.checked - verified.invalid) as f64,
+            ),
+            ("result=\"invalid\"", verified.invalid as f64),
+        ],
+    );
+    let windows = ["10s", "60s", "15m"];
+    let labels = windows.map(|window| format!("window=\"{}\"", window));
+    let hashrates: Vec<_> = labels
+        .iter()
+        .zip(stats.hashrate())
+        .filter_map(|(label, rate)| Some((label.as_str(), rate?)))
+        .collect();
+    metric(
+        &mut out,
+        "orng_hashrate",
+        "gauge",
+        "Total hashes per second, averaged over the window.",
+        &hashrates,
+    );
+    metric(
+        &mut out,
+        "orng_pool_connected",
+        "gauge",
+        "Whether the miner is connected to its pool or daemon.",
+        &[("", connected as u8 as f64)],
+    );
+    metric(
+        &mut out,
+        "orng_threads_failed",
+        "gauge",
+        "Mining threads that could not set up a VM and hash nothing.",
+        &[("", stats.failed_threads().len() as f64)],
+    );
+    if let Some(duration) = stats.dataset_init() {
+        metric(
+            &mut out,
+            "orng_dataset_init_seconds",
+            "gauge",
+            "Time the most recent RandomX cache and dataset took to initialise.",
+            &[("", duration.as_secs_f64())],
+        );
+    }
+    if let Some(job) = stats.job() {
+        metric(
+            &mut out,
+            "orng_job_age_seconds",
+            "gauge",
+            "Time since the current job was received.",
+            &[("", job.received.elapsed().as_secs_f64())],
+        );
+    }
+    out
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, values: &[(&str, f64)]) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    for (labels, value) in values {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{} {}", name, value);
+        } else {
+            let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::share::ShareOutcome;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let stats = Stats::new(2);
+        stats.add_hashes(0, 7);
+        stats.add_hashes(1, 3);
+        stats.record_outcome(&ShareOutcome::Accepted);
+        stats.record_outcome(&ShareOutcome::Accepted);
+        stats.record_outcome(&ShareOutcome::Stale);
+        stats.record_state("pool:3333".into(), ConnectionState::Connected);
+        stats.record_dataset_init(Duration::from_millis(1500));
+
+        let text = render(&stats);
+        assert!(text.contains("# TYPE orng_hashes_total counter\norng_hashes_total 10\n"));
+        assert!(text.contains("orng_shares_total{result=\"accepted\"} 2\n"));
+        assert!(text.contains("orng_shares_total{result=\"stale\"} 1\n"));
+        assert!(text.contains("orng_shares_total{result=\"rejected\"} 0\n"));
+        assert!(text.contains("orng_pool_connected 1\n"));
+        assert!(text.contains("orng_threads_failed 0\n"));
+        assert!(text.contains("orng_dataset_init_seconds 1.5\n"));
+        assert!(text.contains("# TYPE orng_hashrate gauge\n"));
+        // No job yet.
+        assert!(!text.contains("orng_job_age_seconds"));
+    }
+}
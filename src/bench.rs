@@ -0,0 +1,173 @@
+use crate::{
+    cpu::CpuOptions,
+    error::Result,
+    event,
+    job::Job,
+    selftest,
+    stats::Stats,
+    target::{Difficulty, Target},
+    worker::Worker,
+};
+
+use randomx_rs::RandomXFlag;
+use std::{
+    fmt,
+    num::NonZeroUsize,
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL: Duration = Duration::from_millis(100);
+
+/// Seed of the benchmark job. It keys some of the RandomX test vectors, so
+/// the benchmarked dataset can check them.
+pub const TEST_KEY: &[u8] = b"test key 000";
+
+/// When a benchmark stops.
+#[derive(Debug, Clone, Copy)]
+pub enum Limit {
+    Time(Duration),
+    Hashes(u64),
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub flags: RandomXFlag,
+    pub dataset_init: Option<Duration>,
+    /// How many test vectors were checked, all of which matched.
+    pub verified: Option<usize>,
+    /// Hashes by each thread.
+    pub hashes: Vec<u64>,
+    pub elapsed: Duration,
+}
+
+impl Report {
+    pub fn thread_hashrates(&self) -> Vec<f64> {
+        let secs = self.elapsed.as_secs_f64();
+        self.hashes.iter().map(|&h| h as f64 / secs).collect()
+    }
+
+    pub fn hashrate(&self) -> f64 {
+        self.hashes.iter().sum::<u64>() as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Flags:        {:?}", self.flags)?;
+        if let Some(init) = self.dataset_init {
+            writeln!(f, "Dataset init: {:.2}s", init.as_secs_f64())?;
+        }
+        if let Some(vectors) = self.verified {
+            writeln!(f, "Verify:       {} test vectors OK", vectors)?;
+        }
+        for (i, rate) in self.thread_hashrates().into_iter().enumerate() {
+            writeln!(f, "Thread {:<5} {:.2} H/s", format!("{}:", i), rate)?;
+        }
+        write!(
+            f,
+            "Total:        {:.2} H/s ({} hashes in {:.1}s)",
+            self.hashrate(),
+            self.hashes.iter().sum::<u64>(),
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+// A job no hash will meet, on the test vector key.
+fn job() -> Job {
+    let mut blob = vec![0; 76];
+    blob[0] = 0x10;
+    Job {
+        id: "bench".into(),
+        blob,
+        seed: TEST_KEY.to_vec(),
+        target: Target::from_difficulty(Difficulty(u64::MAX)),
+        next_seed: None,
+        clean: true,
+    }
+}
+
+/// Mines a synthetic job on `threads` threads until `limit`, after checking
+/// the test vectors on the same dataset if `check` is set.
+pub fn run(
+    threads: NonZeroUsize,
+    light: bool,
+    huge_pages: bool,
+    cpu: CpuOptions,
+    limit: Limit,
+    check: bool,
+) -> Result<Report> {
+    let stats = Stats::new(threads.get());
+    // No hash meets the job's target, so nothing is ever sent on `_events`.
+    let (events, _events) = event::channel();
+    let worker = Worker::init(
+        job(),
+        threads,
+        light,
+        huge_pages,
+        cpu,
+        stats.clone(),
+        events,
+    )?;
+    worker.pause();
+    let dataset = worker.dataset()?;
+    let verified = if check {
+        Some(selftest::check(&dataset.vm()?, TEST_KEY)?)
+    } else {
+        None
+    };
+
+    let counters: Vec<_> = (0..threads.get()).map(|i| stats.hash_counter(i)).collect();
+    let count = || -> Vec<u64> { counters.iter().map(|c| c.load(Ordering::Relaxed)).collect() };
+    let before = count();
+    let started = Instant::now();
+    worker.resume();
+    loop {
+        thread::sleep(POLL);
+        let done = match limit {
+            Limit::Time(duration) => started.elapsed() >= duration,
+            Limit::Hashes(n) => stats.hashes_total() - before.iter().sum::<u64>() >= n,
+        };
+        if done {
+            break;
+        }
+    }
+    worker.stop();
+    let elapsed = started.elapsed();
+
+    Ok(Report {
+        flags: dataset.flags(),
+        dataset_init: stats.dataset_init(),
+        verified,
+        hashes: count().iter().zip(before).map(|(a, b)| a - b).collect(),
+        elapsed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_light() {
+        let report = run(
+            NonZeroUsize::new(1).unwrap(),
+            true,
+            false,
+            CpuOptions::default(),
+            Limit::Hashes(3),
+            true,
+        )
+        .unwrap();
+        assert_eq!(report.verified, Some(3));
+        assert!(!report.flags.contains(RandomXFlag::FLAG_FULL_MEM));
+        assert_eq!(report.hashes.len(), 1);
+        assert!(report.hashes[0] >= 3);
+        assert!(report.hashrate() > 0.0);
+        assert!(report
+            .to_string()
+            .contains("Verify:       3 test vectors OK"));
+    }
+}
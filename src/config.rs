@@ -0,0 +1,326 @@
+use crate::{
+    address::Address,
+    cpu::Argon2Impl,
+    error::{Error, Result},
+    tracker::StalePolicy,
+};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    num::NonZeroUsize,
+    path::Path,
+    str::FromStr,
+};
+
+/// Miner settings, as read from a `--config` file in TOML or JSON.
+///
+/// Every key is optional in the file and takes the default below when
+/// missing. Command-line flags and `ORNG_*` environment variables override
+/// the file, in that order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Pools in order of priority.
+    pub pools: Vec<PoolConfig>,
+    /// monerod RPC endpoint to mine solo against instead of the pools.
+    pub daemon: Option<String>,
+    /// Address solo-mined blocks pay out to.
+    pub wallet: Option<String>,
+    /// Worker threads, all cores by default.
+    pub threads: NonZeroUsize,
+    /// Cores to pin worker threads to in turn, all cores by default.
+    pub affinity: Option<Vec<usize>>,
+    /// Light mode: hash from the 256 MiB cache instead of the 2 GiB dataset.
+    pub light: bool,
+    pub huge_pages: bool,
+    pub no_jit: bool,
+    pub soft_aes: bool,
+    pub argon2_impl: Argon2Impl,
+    /// Recompute every share with the RandomX interpreter before submitting
+    /// it, and drop those that do not verify.
+    pub verify_shares: bool,
+    /// Percentage of invalid shares among the last 100 verified above which
+    /// an error is logged.
+    pub invalid_alert: f64,
+    /// What to do with shares found for a job that was replaced.
+    pub stale_policy: StalePolicy,
+    /// Milliseconds such shares are still submitted for under the `grace`
+    /// policy.
+    pub stale_grace_ms: u64,
+    pub tls_insecure: bool,
+    /// SHA-256 fingerprint (hex) every pool certificate has to match.
+    pub tls_fingerprint: Option<String>,
+    /// Consecutive failed reconnect rounds before giving up, forever by
+    /// default.
+    pub retries: Option<u32>,
+    /// Seconds between attempts to switch back to a higher-priority pool.
+    pub probe_interval: u64,
+    pub http: HttpConfig,
+    /// One of `error`, `warn`, `info`, `debug` or `trace`.
+    pub log_level: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PoolConfig {
+    pub url: String,
+    #[serde(default)]
+    pub user: String,
+    #[serde(default = "default_pass")]
+    pub pass: String,
+}
+
+/// The JSON API, served only if `port` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub port: Option<u16>,
+    pub host: IpAddr,
+    /// Bearer token requests have to carry; also enables the control API.
+    pub token: Option<String>,
+}
+
+fn default_pass() -> String {
+    "x".into()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pools: Vec::new(),
+            daemon: None,
+            wallet: None,
+            threads: std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+            affinity: None,
+            light: false,
+            huge_pages: false,
+            no_jit: false,
+            soft_aes: false,
+            argon2_impl: Argon2Impl::Auto,
+            verify_shares: false,
+            invalid_alert: 5.0,
+            stale_policy: StalePolicy::Drop,
+            stale_grace_ms: 2000,
+            tls_insecure: false,
+            tls_fingerprint: None,
+            retries: None,
+            probe_interval: 120,
+            http: HttpConfig::default(),
+            log_level: "debug".into(),
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            token: None,
+        }
+    }
+}
+
+impl Config {
+    /// Reads `path` as JSON if it ends in `.json` and as TOML otherwise.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+        let config = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())
+        };
+        config.map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Checks that there is somewhere to mine and a valid address to mine
+    /// to. There are deliberately no defaults for either. Also checks that
+    /// numbers are in range.
+    pub fn validate(&self) -> Result<()> {
+        if !(0.0..=100.0).contains(&self.invalid_alert) {
+            return Err(Error::Config(format!(
+                "invalid_alert is a percentage, got {}",
+                self.invalid_alert
+            )));
+        }
+        if self.daemon.is_some() {
+            let wallet = self.wallet.as_deref().ok_or_else(|| {
+                Error::Config(
+                    "No wallet for solo mining: pass --user or set wallet in the config file"
+                        .into(),
+                )
+            })?;
+            wallet.parse::<Address>()?;
+            return Ok(());
+        }
+        if self.pools.is_empty() {
+            return Err(Error::Config(
+                "No pool configured: pass --url or add [[pools]] to the config file".into(),
+            ));
+        }
+        for pool in &self.pools {
+            if pool.user.is_empty() {
+                return Err(Error::Config(format!(
+                    "No wallet address for {}: pass --user or set user in the config file",
+                    pool.url
+                )));
+            }
+            Address::from_login(&pool.user)?;
+        }
+        Ok(())
+    }
+
+    /// The configuration as TOML, with the HTTP API token redacted since it
+    /// grants control of the miner.
+    pub fn to_toml(&self) -> Result<String> {
+        let mut config = self.clone();
+        if let Some(token) = config.http.token.as_mut() {
+            *token = "<redacted>".into();
+        }
+        toml::to_string_pretty(&config).map_err(|e| Error::Config(e.to_string()))
+    }
+
+    pub fn log_level(&self) -> Result<tracing::Level> {
+        tracing::Level::from_str(&self.log_level)
+            .map_err(|_| Error::Config(format!("Unknown log level {}", self.log_level)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("orng-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_toml_and_json() {
+        let toml = write(
+            "config.toml",
+            r#"
+            threads = 3
+            huge_pages = true
+            affinity = [0, 2, 4]
+            argon2_impl = "avx2"
+            stale_policy = "grace"
+            log_level = "info"
+
+            [[pools]]
+            url = "stratum+ssl://primary:443"
+            user = "wallet"
+
+            [[pools]]
+            url = "stratum+tcp://backup:3333"
+
+            [http]
+            port = 8080
+            "#,
+        );
+        let config = Config::load(&toml).unwrap();
+        assert_eq!(config.threads.get(), 3);
+        assert!(config.huge_pages);
+        assert_eq!(config.affinity, Some(vec![0, 2, 4]));
+        assert_eq!(config.argon2_impl, Argon2Impl::Avx2);
+        assert_eq!(config.stale_policy, StalePolicy::Grace);
+        assert_eq!(config.stale_grace_ms, 2000);
+        assert_eq!(config.log_level().unwrap(), tracing::Level::INFO);
+        assert_eq!(config.pools.len(), 2);
+        assert_eq!(config.pools[1].user, "");
+        assert_eq!(config.pools[1].pass, "x");
+        assert_eq!(config.http.port, Some(8080));
+        // Missing keys keep their defaults.
+        assert_eq!(config.http.host, HttpConfig::default().host);
+        assert_eq!(config.probe_interval, 120);
+
+        let json = write(
+            "config.json",
+            &serde_json::to_string(&serde_json::json!({ "threads": 3, "light": true })).unwrap(),
+        );
+        let config = Config::load(&json).unwrap();
+        assert_eq!(config.threads.get(), 3);
+        assert!(config.light);
+        assert_eq!(config.pools, Config::default().pools);
+
+        let typo = write("typo.toml", "hugepages = true");
+        assert!(matches!(Config::load(&typo), Err(Error::Config(_))));
+
+        for path in [toml, json, typo] {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let wallet = "44qARb3o5kWimeStvm9g4r5kTCMSZio8SEWDcEy9HKnnXg6iQns7Mqi4SrrSNZV6mG1YQWqRgr5Lph1BxfQFK8Kz8hMidXR";
+        let pool = |user: &str| PoolConfig {
+            url: "pool:3333".into(),
+            user: user.into(),
+            pass: default_pass(),
+        };
+        let invalid = |config: Config| matches!(config.validate(), Err(Error::Config(_)));
+
+        assert!(invalid(Config::default()));
+        assert!(invalid(Config {
+            pools: vec![pool(wallet)],
+            invalid_alert: 150.0,
+            ..Default::default()
+        }));
+        assert!(invalid(Config {
+            pools: vec![pool("")],
+            ..Default::default()
+        }));
+        assert!(invalid(Config {
+            pools: vec![pool(&wallet[1..])],
+            ..Default::default()
+        }));
+        assert!(Config {
+            pools: vec![pool(&format!("{}.rig1", wallet))],
+            ..Default::default()
+        }
+        .validate()
+        .is_ok());
+
+        let solo = Config {
+            daemon: Some("http://127.0.0.1:18081".into()),
+            ..Default::default()
+        };
+        assert!(invalid(solo.clone()));
+        assert!(Config {
+            wallet: Some(wallet.into()),
+            ..solo
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn test_dump_round_trips() {
+        let config = Config {
+            pools: vec![PoolConfig {
+                url: "pool:3333".into(),
+                user: "wallet".into(),
+                pass: default_pass(),
+            }],
+            daemon: Some("http://127.0.0.1:18081".into()),
+            retries: Some(5),
+            ..Default::default()
+        };
+        let dumped = config.to_toml().unwrap();
+        assert_eq!(toml::from_str::<Config>(&dumped).unwrap(), config);
+    }
+
+    #[test]
+    fn test_dump_redacts_token() {
+        let mut config = Config::default();
+        config.http.token = Some("secret".into());
+        let dumped = config.to_toml().unwrap();
+        assert!(!dumped.contains("secret"));
+        assert!(dumped.contains(r#"token = "<redacted>""#));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{
+    error::{Error, Result},
+    event::Event,
+};
+
+use serde::Deserialize;
+use std::{
+    num::NonZeroUsize,
+    sync::mpsc::{self, Sender},
+    time::Duration,
+};
+
+// How long a controller waits for the mining loop to carry out a command.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A change to a running miner, carried out by the mining loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Command {
+    /// Stops hashing. Threads keep their VMs so resuming is instant.
+    Pause,
+    Resume,
+    /// Grows or shrinks the worker thread pool.
+    SetThreads {
+        threads: NonZeroUsize,
+    },
+    /// Moves mining to the pool at `index` in the configured list.
+    SwitchPool {
+        index: usize,
+    },
+    /// Stops mining and ends the mining loop. Not available over the API.
+    #[serde(skip)]
+    Shutdown,
+}
+
+/// A command waiting for the mining loop, which answers it through `reply`.
+#[derive(Debug)]
+pub struct Request {
+    pub command: Command,
+    pub reply: Sender<Result<()>>,
+}
+
+/// Handle for sending commands to the mining loop from other threads.
+#[derive(Debug, Clone)]
+pub struct Controller {
+    tx: Sender<Event>,
+}
+
+impl Controller {
+    /// Sends commands as `Event::Command` to the mining loop taking events
+    /// from `events`.
+    pub fn new(events: Sender<Event>) -> Self {
+        Self { tx: events }
+    }
+
+    /// Sends `command` and waits until the mining loop has carried it out.
+    pub fn send(&self, command: Command) -> Result<()> {
+        let (reply, reply_rx) = mpsc::channel();
+        self.tx.send(Event::Command(Request { command, reply }))?;
+        reply_rx
+            .recv_timeout(REPLY_TIMEOUT)
+            .map_err(|e| Error::Channel(format!("No reply to {:?}: {}", command, e)))?
+    }
+
+    /// Sends `command` without waiting for it to be carried out.
+    pub fn post(&self, command: Command) -> Result<()> {
+        let (reply, _) = mpsc::channel();
+        self.tx.send(Event::Command(Request { command, reply }))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_command_from_json() {
+        let parse = |s| serde_json::from_str::<Command>(s).unwrap();
+        assert_eq!(parse(r#"{"method":"pause"}"#), Command::Pause);
+        assert_eq!(
+            parse(r#"{"method":"set_threads","params":{"threads":4}}"#),
+            Command::SetThreads {
+                threads: NonZeroUsize::new(4).unwrap()
+            }
+        );
+        assert_eq!(
+            parse(r#"{"method":"switch_pool","params":{"index":1}}"#),
+            Command::SwitchPool { index: 1 }
+        );
+        assert!(serde_json::from_str::<Command>(
+            r#"{"method":"set_threads","params":{"threads":0}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<Command>(r#"{"method":"shutdown"}"#).is_err());
+    }
+
+    #[test]
+    fn test_send_waits_for_reply() {
+        let (events, event_rx) = crate::event::channel();
+        let controller = Controller::new(events);
+        let handle = thread::spawn(move || {
+            let Event::Command(request) = event_rx.recv().unwrap() else {
+                panic!("expected a command");
+            };
+            assert_eq!(request.command, Command::Resume);
+            request.reply.send(Ok(())).unwrap();
+        });
+        controller.send(Command::Resume).unwrap();
+        handle.join().unwrap();
+        assert!(controller.send(Command::Pause).is_err());
+    }
+}
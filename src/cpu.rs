@@ -0,0 +1,283 @@
+use randomx_rs::RandomXFlag;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How generated code can be made executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jit {
+    /// Pages may be writable and executable at the same time.
+    Enabled,
+    /// W^X is enforced, pages have to be flipped between RW and RX.
+    Secure,
+    /// No JIT compiler for this architecture or executable pages are denied.
+    Unavailable,
+}
+
+/// Argon2 implementation used to fill the RandomX cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Argon2Impl {
+    /// Fastest implementation the CPU supports
+    #[default]
+    Auto,
+    /// Portable reference implementation
+    Ref,
+    Ssse3,
+    Avx2,
+}
+
+/// CPU and OS capabilities relevant to RandomX.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuFeatures {
+    pub aes: bool,
+    pub avx2: bool,
+    pub ssse3: bool,
+    pub jit: Jit,
+}
+
+/// Overrides for the detected configuration.
+#[derive(Debug, Clone, Default)]
+pub struct CpuOptions {
+    pub no_jit: bool,
+    pub soft_aes: bool,
+    pub argon2: Argon2Impl,
+    /// Cores to pin worker threads to in turn, instead of all of them.
+    pub affinity: Option<Vec<usize>>,
+}
+
+impl CpuFeatures {
+    /// Detects what this machine supports. Features RandomX was built without
+    /// count as unsupported.
+    pub fn detect() -> Self {
+        let supported = RandomXFlag::get_recommended_flags();
+        let jit = if supported.contains(RandomXFlag::FLAG_JIT) {
+            probe_jit()
+        } else {
+            Jit::Unavailable
+        };
+        Self {
+            aes: supported.contains(RandomXFlag::FLAG_HARD_AES) && has_feature("aes"),
+            avx2: supported.contains(RandomXFlag::FLAG_ARGON2_AVX2) && has_feature("avx2"),
+            ssse3: supported.contains(RandomXFlag::FLAG_ARGON2_SSSE3) && has_feature("ssse3"),
+            jit,
+        }
+    }
+
+    /// RandomX flags for these features with `options` applied.
+    pub fn flags(&self, options: &CpuOptions) -> RandomXFlag {
+        let mut flags = RandomXFlag::FLAG_DEFAULT;
+        if self.aes && !options.soft_aes {
+            flags |= RandomXFlag::FLAG_HARD_AES;
+        }
+        if !options.no_jit {
+            match self.jit {
+                Jit::Enabled => flags |= RandomXFlag::FLAG_JIT,
+                Jit::Secure => flags |= RandomXFlag::FLAG_JIT | RandomXFlag::FLAG_SECURE,
+                Jit::Unavailable => {}
+            }
+        }
+        flags | self.argon2_flag(options.argon2)
+    }
+
+    fn argon2_flag(&self, argon2: Argon2Impl) -> RandomXFlag {
+        match argon2 {
+            Argon2Impl::Ref => RandomXFlag::FLAG_DEFAULT,
+            Argon2Impl::Avx2 if self.avx2 => RandomXFlag::FLAG_ARGON2_AVX2,
+            Argon2Impl::Ssse3 if self.ssse3 => RandomXFlag::FLAG_ARGON2_SSSE3,
+            Argon2Impl::Avx2 | Argon2Impl::Ssse3 => {
+                tracing::warn!(
+                    "Argon2 {:?} is not supported on this CPU, picking automatically",
+                    argon2
+                );
+                self.argon2_flag(Argon2Impl::Auto)
+            }
+            Argon2Impl::Auto if self.avx2 => RandomXFlag::FLAG_ARGON2_AVX2,
+            Argon2Impl::Auto if self.ssse3 => RandomXFlag::FLAG_ARGON2_SSSE3,
+            Argon2Impl::Auto => RandomXFlag::FLAG_DEFAULT,
+        }
+    }
+}
+
+impl fmt::Display for CpuFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        write!(
+            f,
+            "AES {}, AVX2 {}, SSSE3 {}, JIT {}",
+            yes_no(self.aes),
+            yes_no(self.avx2),
+            yes_no(self.ssse3),
+            match self.jit {
+                Jit::Enabled => "yes",
+                Jit::Secure => "W^X only",
+                Jit::Unavailable => "no",
+            }
+        )
+    }
+}
+
+/// Detects the CPU, applies `options` and logs the resulting configuration.
+pub fn configure(options: &CpuOptions) -> RandomXFlag {
+    let features = CpuFeatures::detect();
+    let flags = features.flags(options);
+    tracing::info!("CPU: {}", features);
+    tracing::info!(
+        "RandomX: {} AES, {}, Argon2 {}",
+        if flags.contains(RandomXFlag::FLAG_HARD_AES) {
+            "hardware"
+        } else {
+            "software"
+        },
+        if !flags.contains(RandomXFlag::FLAG_JIT) {
+            "interpreter"
+        } else if flags.contains(RandomXFlag::FLAG_SECURE) {
+            "JIT (W^X)"
+        } else {
+            "JIT"
+        },
+        if flags.contains(RandomXFlag::FLAG_ARGON2_AVX2) {
+            "AVX2"
+        } else if flags.contains(RandomXFlag::FLAG_ARGON2_SSSE3) {
+            "SSSE3"
+        } else {
+            "reference"
+        }
+    );
+    if features.aes && options.soft_aes {
+        tracing::warn!("Hardware AES disabled by --soft-aes");
+    }
+    if features.jit != Jit::Unavailable && options.no_jit {
+        tracing::warn!("JIT disabled by --no-jit, hashing will be much slower");
+    }
+    flags
+}
+
+#[cfg(target_arch = "x86_64")]
+fn has_feature(feature: &str) -> bool {
+    match feature {
+        "aes" => std::arch::is_x86_feature_detected!("aes"),
+        "avx2" => std::arch::is_x86_feature_detected!("avx2"),
+        "ssse3" => std::arch::is_x86_feature_detected!("ssse3"),
+        _ => false,
+    }
+}
+
+// Elsewhere RandomX's own detection is all there is to go by.
+#[cfg(not(target_arch = "x86_64"))]
+fn has_feature(_feature: &str) -> bool {
+    true
+}
+
+/// Maps a page and checks what the OS lets us do with it, the same way the
+/// RandomX JIT does.
+#[cfg(unix)]
+fn probe_jit() -> Jit {
+    // SAFETY: a fresh anonymous private mapping that is only reprotected and
+    // unmapped here.
+    unsafe {
+        let size = 4096;
+        let page = libc::mmap(
+            std::ptr::null_mut(),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        );
+        if page == libc::MAP_FAILED {
+            return Jit::Unavailable;
+        }
+        let jit = if libc::mprotect(
+            page,
+            size,
+            libc::PROT_READ | libc::PROT_WRITE | libc::PROT_EXEC,
+        ) == 0
+        {
+            Jit::Enabled
+        } else if libc::mprotect(page, size, libc::PROT_READ | libc::PROT_EXEC) == 0 {
+            Jit::Secure
+        } else {
+            Jit::Unavailable
+        };
+        libc::munmap(page, size);
+        jit
+    }
+}
+
+#[cfg(not(unix))]
+fn probe_jit() -> Jit {
+    Jit::Enabled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: CpuFeatures = CpuFeatures {
+        aes: true,
+        avx2: true,
+        ssse3: true,
+        jit: Jit::Enabled,
+    };
+
+    #[test]
+    fn test_flags_follow_features() {
+        let flags = ALL.flags(&CpuOptions::default());
+        assert_eq!(
+            flags,
+            RandomXFlag::FLAG_HARD_AES | RandomXFlag::FLAG_JIT | RandomXFlag::FLAG_ARGON2_AVX2
+        );
+
+        let none = CpuFeatures {
+            aes: false,
+            avx2: false,
+            ssse3: false,
+            jit: Jit::Unavailable,
+        };
+        assert_eq!(
+            none.flags(&CpuOptions::default()),
+            RandomXFlag::FLAG_DEFAULT
+        );
+
+        let wx = CpuFeatures {
+            jit: Jit::Secure,
+            avx2: false,
+            ..ALL
+        };
+        assert_eq!(
+            wx.flags(&CpuOptions::default()),
+            RandomXFlag::FLAG_HARD_AES
+                | RandomXFlag::FLAG_JIT
+                | RandomXFlag::FLAG_SECURE
+                | RandomXFlag::FLAG_ARGON2_SSSE3
+        );
+    }
+
+    #[test]
+    fn test_flags_overrides() {
+        let options = CpuOptions {
+            no_jit: true,
+            soft_aes: true,
+            argon2: Argon2Impl::Ref,
+            ..Default::default()
+        };
+        assert_eq!(ALL.flags(&options), RandomXFlag::FLAG_DEFAULT);
+
+        let options = CpuOptions {
+            argon2: Argon2Impl::Ssse3,
+            ..Default::default()
+        };
+        assert!(ALL.flags(&options).contains(RandomXFlag::FLAG_ARGON2_SSSE3));
+        assert!(!ALL.flags(&options).contains(RandomXFlag::FLAG_ARGON2_AVX2));
+
+        // An unsupported implementation falls back to the best available one.
+        let no_avx2 = CpuFeatures { avx2: false, ..ALL };
+        let options = CpuOptions {
+            argon2: Argon2Impl::Avx2,
+            ..Default::default()
+        };
+        assert!(no_avx2
+            .flags(&options)
+            .contains(RandomXFlag::FLAG_ARGON2_SSSE3));
+    }
+}
@@ -0,0 +1,576 @@
+use crate::{
+    error::{DaemonError, Error, Result},
+    event::Event,
+    job::{nonce_offset, optional_from_hex, Job},
+    share::{Share, ShareOutcome},
+    source::WorkSource,
+    stratum::{ConnectionState, ReconnectPolicy},
+    target::{Difficulty, Target},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// New transactions only change the template, not the chain tip, so it is
+// refreshed this often to get them into the block.
+const TEMPLATE_REFRESH: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Templates of jobs that may still have shares in flight.
+const KEEP_TEMPLATES: usize = 4;
+
+#[derive(Debug, Deserialize)]
+struct BlockTemplate {
+    #[serde(with = "hex")]
+    blocktemplate_blob: Vec<u8>,
+    #[serde(with = "hex")]
+    blockhashing_blob: Vec<u8>,
+    difficulty: u64,
+    height: u64,
+    prev_hash: String,
+    #[serde(with = "hex")]
+    seed_hash: Vec<u8>,
+    #[serde(default, deserialize_with = "optional_from_hex")]
+    next_seed_hash: Option<Vec<u8>>,
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubmitResult {
+    status: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcRequest<'a, P> {
+    jsonrpc: &'static str,
+    id: &'static str,
+    method: &'a str,
+    params: P,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<R> {
+    result: Option<R>,
+    error: Option<RpcError>,
+}
+
+// JSON-RPC endpoint of a monerod instance.
+#[derive(Debug, Clone)]
+struct Rpc {
+    agent: ureq::Agent,
+    url: String,
+}
+
+impl Rpc {
+    fn call<P: Serialize, R: DeserializeOwned>(&self, method: &str, params: P) -> Result<R> {
+        let response: RpcResponse<R> = self
+            .agent
+            .post(&format!("{}/json_rpc", self.url))
+            .send_json(RpcRequest {
+                jsonrpc: "2.0",
+                id: "0",
+                method,
+                params,
+            })
+            .map_err(|e| match timed_out(&e) {
+                true => DaemonError::Timeout,
+                false => DaemonError::Http(e.to_string()),
+            })?
+            .into_json()?;
+        match response {
+            RpcResponse {
+                error: Some(RpcError { code, message }),
+                ..
+            } => Err(DaemonError::Rpc { code, message }.into()),
+            RpcResponse {
+                result: Some(result),
+                ..
+            } => Ok(result),
+            RpcResponse { .. } => Err(DaemonError::Status("empty response".into()).into()),
+        }
+    }
+
+    fn block_template(&self, wallet: &str) -> Result<BlockTemplate> {
+        let template: BlockTemplate = self.call(
+            "get_block_template",
+            serde_json::json!({ "wallet_address": wallet, "reserve_size": 0 }),
+        )?;
+        if template.status != "OK" {
+            return Err(DaemonError::Status(template.status).into());
+        }
+        Ok(template)
+    }
+
+    fn submit_block(&self, blob: &[u8]) -> Result<()> {
+        let result: SubmitResult = self.call("submit_block", [hex::encode(blob)])?;
+        if result.status != "OK" {
+            return Err(DaemonError::Status(result.status).into());
+        }
+        Ok(())
+    }
+}
+
+// Whether a request failed because the daemon took too long, rather than
+// because it could not be reached at all.
+fn timed_out(error: &ureq::Error) -> bool {
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            return matches!(
+                error.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            );
+        }
+        source = error.source();
+    }
+    false
+}
+
+static NEXT_JOB: AtomicU64 = AtomicU64::new(1);
+
+// Block templates of recent jobs, newest last, to assemble found blocks from.
+type Templates = Arc<Mutex<VecDeque<(String, Vec<u8>)>>>;
+
+/// Solo mining against a monerod JSON-RPC endpoint: jobs come from
+/// `get_block_template`, and every share meets the network difficulty and is
+/// sent back as a block through `submit_block`. Polling stops when the
+/// `Daemon` is closed or dropped.
+#[derive(Debug)]
+pub struct Daemon {
+    rpc: Rpc,
+    templates: Templates,
+    events: Sender<Event>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Daemon {
+    /// Fetches the first block template paying to `wallet` from the daemon
+    /// at `url`, e.g. `http://127.0.0.1:18081`, and keeps polling for new
+    /// ones from then on. Failed polls are retried with the backoff and
+    /// attempt limit of `reconnect`. Jobs, block verdicts and connection
+    /// changes are sent to `events`.
+    #[tracing::instrument(skip(wallet, reconnect, events))]
+    pub fn connect(
+        url: &str,
+        wallet: &str,
+        reconnect: ReconnectPolicy,
+        events: Sender<Event>,
+    ) -> Result<Self> {
+        let rpc = Rpc {
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            url: url.trim_end_matches('/').to_string(),
+        };
+        let template = rpc.block_template(wallet)?;
+        tracing::info!("Mining solo on {} at height {}", rpc.url, template.height);
+
+        let templates = Templates::default();
+        let tip = (template.height, template.prev_hash.clone());
+        events
+            .send(Event::Job(add_template(&templates, template, true)))
+            .map_err(Error::from)?;
+        events
+            .send(Event::State(ConnectionState::Connected))
+            .map_err(Error::from)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let poller = Poller {
+            rpc: rpc.clone(),
+            wallet: wallet.to_string(),
+            templates: templates.clone(),
+            tip,
+            policy: reconnect,
+            events: events.clone(),
+            stop: stop.clone(),
+        };
+        thread::spawn(move || {
+            let span = tracing::info_span!("daemon");
+            let _enter = span.enter();
+            poller.run();
+        });
+        Ok(Self {
+            rpc,
+            templates,
+            events,
+            stop,
+        })
+    }
+
+    /// Puts the nonce of `share` into its block template and submits the
+    /// block. The daemon's verdict is sent later as an `Event::Outcome`.
+    pub fn submit(&mut self, share: Share) -> Result<()> {
+        let template = self
+            .templates
+            .lock()
+            .map_err(|_| Error::Thread("Template lock poisoned".into()))?
+            .iter()
+            .find(|(id, _)| *id == share.job_id)
+            .map(|(_, blob)| blob.clone());
+        let Some(mut blob) = template else {
+            // The chain moved on too far for the block to be of any use.
+            let _ = self.events.send(Event::Outcome(share, ShareOutcome::Stale));
+            return Ok(());
+        };
+        let offset = nonce_offset(&blob)
+            .ok_or_else(|| DaemonError::Status("malformed block template".into()))?;
+        blob[offset..offset + 4].copy_from_slice(&share.nonce);
+
+        let rpc = self.rpc.clone();
+        let events = self.events.clone();
+        thread::spawn(move || {
+            let outcome = match rpc.submit_block(&blob) {
+                Ok(()) => {
+                    tracing::info!("Block accepted by the daemon");
+                    ShareOutcome::Accepted
+                }
+                Err(Error::Daemon(DaemonError::Rpc { code, message })) => {
+                    ShareOutcome::from_error(code, message)
+                }
+                Err(Error::Daemon(DaemonError::Timeout)) => {
+                    tracing::warn!("The daemon did not answer the block in time");
+                    ShareOutcome::Timeout
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to submit block: {}", e);
+                    ShareOutcome::Rejected {
+                        code: 0,
+                        message: e.to_string(),
+                    }
+                }
+            };
+            let _ = events.send(Event::Outcome(share, outcome));
+        });
+        Ok(())
+    }
+
+    pub fn url(&self) -> &str {
+        &self.rpc.url
+    }
+}
+
+impl WorkSource for Daemon {
+    fn submit(&mut self, share: Share) -> Result<()> {
+        Daemon::submit(self, share)
+    }
+
+    // Every request is a fresh HTTP call, there is no connection to keep open.
+    fn heartbeat(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn endpoint(&self) -> String {
+        self.rpc.url.clone()
+    }
+
+    fn close(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// Polls the daemon for new templates until `stop` is set.
+struct Poller {
+    rpc: Rpc,
+    wallet: String,
+    templates: Templates,
+    // Height and previous block of the first template.
+    tip: (u64, String),
+    policy: ReconnectPolicy,
+    events: Sender<Event>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Poller {
+    fn run(self) {
+        let mut tip = Some(self.tip.clone());
+        let mut updated = Instant::now();
+        let mut failures = 0;
+        let mut wait = POLL_INTERVAL;
+        loop {
+            thread::sleep(wait);
+            if self.stop.load(Ordering::Relaxed) {
+                return;
+            }
+            let template = match self.rpc.block_template(&self.wallet) {
+                Ok(template) => template,
+                Err(e) => {
+                    // Like a pool connection: the first failure loses the
+                    // daemon, and each retry after it is an attempt.
+                    failures += 1;
+                    tracing::warn!("Failed to get block template: {}", e);
+                    if self.policy.exhausted(failures) {
+                        tracing::error!("Giving up after {} attempts", failures - 1);
+                        let _ = self.events.send(Event::State(ConnectionState::Failed));
+                        return;
+                    }
+                    wait = self.policy.delay(failures);
+                    let state = ConnectionState::Reconnecting {
+                        attempt: failures,
+                        delay: wait,
+                    };
+                    if self.events.send(Event::State(state)).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+            wait = POLL_INTERVAL;
+            if failures > 0 {
+                failures = 0;
+                if self
+                    .events
+                    .send(Event::State(ConnectionState::Connected))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+
+            let new_tip = Some((template.height, template.prev_hash.clone()));
+            if new_tip == tip && updated.elapsed() < TEMPLATE_REFRESH {
+                continue;
+            }
+            // A refreshed template on the same tip leaves blocks from the
+            // previous one valid.
+            let clean = new_tip != tip;
+            if clean {
+                tracing::info!("New block template at height {}", template.height);
+            }
+            tip = new_tip;
+            updated = Instant::now();
+            if self
+                .events
+                .send(Event::Job(add_template(&self.templates, template, clean)))
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+fn add_template(templates: &Templates, template: BlockTemplate, clean: bool) -> Job {
+    let id = format!(
+        "{}-{}",
+        template.height,
+        NEXT_JOB.fetch_add(1, Ordering::Relaxed)
+    );
+    let mut templates = templates.lock().unwrap_or_else(|e| e.into_inner());
+    if templates.len() == KEEP_TEMPLATES {
+        templates.pop_front();
+    }
+    templates.push_back((id.clone(), template.blocktemplate_blob));
+    Job {
+        id,
+        blob: template.blockhashing_blob,
+        seed: template.seed_hash,
+        target: Target::from_difficulty(Difficulty(template.difficulty)),
+        next_seed: template.next_seed_hash,
+        clean,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::share::test_share;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::mpsc::{self, Receiver},
+    };
+
+    fn next_outcome(events: &Receiver<Event>) -> (Share, ShareOutcome) {
+        crate::event::next_outcome(events, REQUEST_TIMEOUT)
+    }
+
+    // Major and minor version 16, a five byte timestamp, the previous block
+    // id and the nonce, followed by the rest of the block.
+    const TEMPLATE: &str = concat!(
+        "1010",
+        "a0b1c2d306",
+        "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        "00000000",
+        "0102"
+    );
+
+    // Stands in for monerod: answers get_block_template with `TEMPLATE` and
+    // forwards the blobs passed to submit_block.
+    fn stand_in(submitted: Sender<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                let result = match request["method"].as_str().unwrap() {
+                    "get_block_template" => serde_json::json!({
+                        "blocktemplate_blob": TEMPLATE,
+                        "blockhashing_blob": "0707",
+                        "difficulty": 1000,
+                        "height": 3000000,
+                        "prev_hash": "aa",
+                        "seed_hash": "bb",
+                        "next_seed_hash": "",
+                        "status": "OK",
+                    }),
+                    _ => {
+                        let blob = request["params"][0].as_str().unwrap().to_string();
+                        let _ = submitted.send(blob);
+                        serde_json::json!({ "status": "OK" })
+                    }
+                };
+                let body = serde_json::json!({ "id": "0", "jsonrpc": "2.0", "result": result })
+                    .to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    // An address nothing listens on.
+    fn unreachable() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    #[test]
+    fn test_poller_gives_up_after_retries() {
+        let (events, event_rx) = crate::event::channel();
+        let poller = Poller {
+            rpc: Rpc {
+                agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+                url: unreachable(),
+            },
+            wallet: "wallet".into(),
+            templates: Templates::default(),
+            tip: (0, String::new()),
+            policy: ReconnectPolicy {
+                initial_delay: Duration::from_millis(10),
+                max_delay: Duration::from_millis(10),
+                max_attempts: Some(2),
+                ..Default::default()
+            },
+            events,
+            stop: Arc::default(),
+        };
+        poller.run();
+        let states: Vec<_> = event_rx
+            .try_iter()
+            .map(|event| match event {
+                Event::State(ConnectionState::Reconnecting { attempt, .. }) => Some(attempt),
+                Event::State(ConnectionState::Failed) => None,
+                event => panic!("unexpected {:?}", event),
+            })
+            .collect();
+        assert_eq!(states, [Some(1), Some(2), None]);
+    }
+
+    #[test]
+    fn test_solo_job_and_submit() {
+        let (submitted_tx, submitted_rx) = mpsc::channel();
+        let (events, event_rx) = crate::event::channel();
+        let mut daemon = Daemon::connect(
+            &stand_in(submitted_tx),
+            "wallet",
+            ReconnectPolicy::default(),
+            events,
+        )
+        .unwrap();
+
+        let Event::Job(job) = event_rx.recv().unwrap() else {
+            panic!("expected the first job");
+        };
+        assert_eq!(job.blob, vec![0x07, 0x07]);
+        assert_eq!(job.seed, vec![0xbb]);
+        assert_eq!(job.next_seed, None);
+        assert_eq!(job.difficulty(), Difficulty(1000));
+
+        daemon.submit(test_share(&job.id, 0x11223344)).unwrap();
+        let blob = submitted_rx.recv_timeout(REQUEST_TIMEOUT).unwrap();
+        assert_eq!(&blob[78..86], "44332211");
+        assert_eq!(blob.len(), TEMPLATE.len());
+        let (share, outcome) = next_outcome(&event_rx);
+        assert_eq!(share.job_id, job.id);
+        assert_eq!(outcome, ShareOutcome::Accepted);
+
+        daemon.submit(test_share("unknown", 0)).unwrap();
+        let (_, outcome) = next_outcome(&event_rx);
+        assert_eq!(outcome, ShareOutcome::Stale);
+
+        // A daemon that cannot be reached rejects the block, one that does
+        // not answer times it out.
+        let submit_to = |daemon: &mut Daemon, url: String| {
+            daemon.rpc.url = url;
+            daemon.submit(test_share(&job.id, 0)).unwrap();
+            next_outcome(&event_rx).1
+        };
+        let ShareOutcome::Rejected { code: 0, message } = submit_to(&mut daemon, unreachable())
+        else {
+            panic!("expected the block to be rejected");
+        };
+        assert!(message.contains("HTTP request failed"));
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        daemon.rpc.agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_millis(200))
+            .build();
+        let outcome = submit_to(
+            &mut daemon,
+            format!("http://{}", silent.local_addr().unwrap()),
+        );
+        assert_eq!(outcome, ShareOutcome::Timeout);
+
+        // Once the poller is gone nothing holds a sender any more.
+        drop(daemon);
+        let deadline = Instant::now() + REQUEST_TIMEOUT;
+        loop {
+            match event_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(_) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                Err(mpsc::RecvTimeoutError::Timeout) => panic!("the poller kept running"),
+            }
+        }
+    }
+}
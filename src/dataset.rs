@@ -0,0 +1,352 @@
+use crate::{error::Result, hugepages::HugePages, stats::Stats};
+
+use randomx_rs::{RandomXCache, RandomXDataset, RandomXError, RandomXFlag, RandomXVM};
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// RandomX cache and, in full-memory mode, dataset for one seed.
+///
+/// Both are read-only once initialised and RandomX allows any number of VMs
+/// on any thread to share them, so every worker thread hashes against the
+/// same copy.
+pub struct Dataset {
+    seed: Vec<u8>,
+    flags: RandomXFlag,
+    cache: RandomXCache,
+    dataset: Option<RandomXDataset>,
+    init_time: Duration,
+}
+
+// SAFETY: the cache and dataset are never written after construction, and
+// randomx-rs keeps them alive through atomically reference-counted handles.
+unsafe impl Send for Dataset {}
+unsafe impl Sync for Dataset {}
+
+impl Dataset {
+    /// Builds the cache and, in full-memory mode, the dataset for `seed`,
+    /// initialising the dataset on every available core.
+    pub fn new(flags: RandomXFlag, seed: &[u8]) -> Result<Self> {
+        let threads = thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+        Self::with_threads(flags, seed, threads)
+    }
+
+    /// Like `new`, but initialises the dataset on `threads` threads, each
+    /// taking one contiguous range of items.
+    #[tracing::instrument(skip_all, fields(seed = %hex::encode(seed)))]
+    pub fn with_threads(flags: RandomXFlag, seed: &[u8], threads: NonZeroUsize) -> Result<Self> {
+        let started = Instant::now();
+        let cache =
+            with_large_pages_fallback("cache", flags, |flags| RandomXCache::new(flags, seed))?;
+        let dataset = if flags.contains(RandomXFlag::FLAG_FULL_MEM) {
+            let dataset = with_large_pages_fallback("dataset", flags, |flags| {
+                RandomXDataset::alloc(flags, cache.clone())
+            })?;
+            init_items(&dataset, threads)?;
+            Some(dataset)
+        } else {
+            None
+        };
+        let init_time = started.elapsed();
+        tracing::info!(
+            "RandomX {} ready in {:.1}s",
+            if dataset.is_some() {
+                "dataset"
+            } else {
+                "cache"
+            },
+            init_time.as_secs_f64()
+        );
+        Ok(Self {
+            seed: seed.to_vec(),
+            flags,
+            cache,
+            dataset,
+            init_time,
+        })
+    }
+
+    pub fn seed(&self) -> &[u8] {
+        &self.seed
+    }
+
+    pub fn flags(&self) -> RandomXFlag {
+        self.flags
+    }
+
+    /// How long the cache and dataset took to initialise.
+    pub fn init_time(&self) -> Duration {
+        self.init_time
+    }
+
+    /// Creates a VM that hashes against this dataset.
+    pub fn vm(&self) -> Result<RandomXVM> {
+        let mut flags = self.flags;
+        // randomx-rs does not check whether the VM was allocated, so only ask
+        // for huge pages when there is known to be room for the scratchpad.
+        let _guard = VM_ALLOC.lock().unwrap_or_else(|e| e.into_inner());
+        if flags.contains(RandomXFlag::FLAG_LARGE_PAGES)
+            && !HugePages::read().is_some_and(|pages| pages.fits_scratchpad())
+        {
+            tracing::warn!("No free huge pages left for the VM scratchpad, using normal pages");
+            flags.remove(RandomXFlag::FLAG_LARGE_PAGES);
+        }
+        Ok(RandomXVM::new(
+            flags,
+            Some(self.cache.clone()),
+            self.dataset.clone(),
+        )?)
+    }
+}
+
+// Initialises every item of `dataset`, splitting them into one range per
+// thread.
+fn init_items(dataset: &RandomXDataset, threads: NonZeroUsize) -> Result<()> {
+    let items = RandomXDataset::count()?;
+    let threads = u32::try_from(threads.get()).unwrap_or(u32::MAX).min(items);
+    let per_thread = items / threads;
+    let shared = Items(dataset);
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let start = i * per_thread;
+                // The last range also takes the remainder.
+                let count = if i == threads - 1 {
+                    items - start
+                } else {
+                    per_thread
+                };
+                let shared = &shared;
+                scope.spawn(move || shared.init(start, count))
+            })
+            .collect();
+        handles.into_iter().try_for_each(|handle| {
+            handle
+                .join()
+                .map_err(|_| RandomXError::Other("Dataset init thread panicked".into()))?
+        })
+    })?;
+    tracing::debug!("Initialised {} dataset items on {} threads", items, threads);
+    Ok(())
+}
+
+// A dataset handed to the init threads.
+struct Items<'a>(&'a RandomXDataset);
+
+// SAFETY: each thread writes a disjoint range of items, and nothing reads the
+// dataset until all of them have finished.
+unsafe impl Sync for Items<'_> {}
+
+impl Items<'_> {
+    fn init(&self, start: u32, count: u32) -> std::result::Result<(), RandomXError> {
+        self.0.init(start, count)
+    }
+}
+
+// Serialises VM creation so the huge page check in `Dataset::vm` is not raced.
+static VM_ALLOC: Mutex<()> = Mutex::new(());
+
+fn with_large_pages_fallback<T>(
+    what: &str,
+    flags: RandomXFlag,
+    alloc: impl Fn(RandomXFlag) -> std::result::Result<T, RandomXError>,
+) -> std::result::Result<T, RandomXError> {
+    match alloc(flags) {
+        Err(e) if flags.contains(RandomXFlag::FLAG_LARGE_PAGES) => {
+            tracing::warn!(
+                "Could not allocate RandomX {} in huge pages ({}), falling back to normal pages",
+                what,
+                e
+            );
+            alloc(flags - RandomXFlag::FLAG_LARGE_PAGES)
+        }
+        result => result,
+    }
+}
+
+// The dataset for the upcoming seed.
+enum Next {
+    None,
+    Building(Vec<u8>),
+    Ready(Arc<Dataset>),
+}
+
+// Datasets under way, behind one lock so threads can wait for them.
+struct Builds {
+    next: Next,
+    // Seeds built by `get` because no dataset for them was prepared.
+    on_demand: Vec<Vec<u8>>,
+}
+
+/// The dataset for the seed currently being mined, shared by all worker
+/// threads, plus the one for the next seed once it has been announced.
+pub struct SharedDataset {
+    flags: RandomXFlag,
+    // Threads datasets are initialised on, one per mining thread.
+    init_threads: AtomicUsize,
+    stats: Arc<Stats>,
+    current: Mutex<Arc<Dataset>>,
+    builds: Mutex<Builds>,
+    built: Condvar,
+}
+
+impl SharedDataset {
+    /// Builds the dataset for `seed` on `threads` threads.
+    pub fn new(
+        flags: RandomXFlag,
+        seed: &[u8],
+        threads: NonZeroUsize,
+        stats: Arc<Stats>,
+    ) -> Result<Self> {
+        let dataset = Dataset::with_threads(flags, seed, threads)?;
+        stats.record_dataset_init(dataset.init_time());
+        Ok(Self {
+            flags,
+            init_threads: AtomicUsize::new(threads.get()),
+            stats,
+            current: Mutex::new(Arc::new(dataset)),
+            builds: Mutex::new(Builds {
+                next: Next::None,
+                on_demand: Vec::new(),
+            }),
+            built: Condvar::new(),
+        })
+    }
+
+    /// Sets how many threads later datasets are initialised on.
+    pub fn set_init_threads(&self, threads: NonZeroUsize) {
+        self.init_threads.store(threads.get(), Ordering::Relaxed);
+    }
+
+    fn build(&self, seed: &[u8]) -> Result<Dataset> {
+        let threads = NonZeroUsize::new(self.init_threads.load(Ordering::Relaxed))
+            .unwrap_or(NonZeroUsize::MIN);
+        let dataset = Dataset::with_threads(self.flags, seed, threads)?;
+        self.stats.record_dataset_init(dataset.init_time());
+        Ok(dataset)
+    }
+
+    /// Returns the dataset for `seed`. A seed change is served from the
+    /// precomputed next dataset when there is one, waiting for it if it is
+    /// still being built; otherwise the first thread to ask builds it while
+    /// the others wait for the result instead of building their own. No lock
+    /// is held while building, so threads still on the current seed carry on,
+    /// and a dataset being prepared for another seed is left to finish.
+    pub fn get(&self, seed: &[u8]) -> Result<Arc<Dataset>> {
+        let mut builds = lock(&self.builds);
+        loop {
+            let mut current = lock(&self.current);
+            if current.seed() == seed {
+                return Ok(current.clone());
+            }
+            let waiting = match &builds.next {
+                Next::Ready(dataset) if dataset.seed() == seed => {
+                    *current = dataset.clone();
+                    builds.next = Next::None;
+                    tracing::info!("Switched to precomputed dataset");
+                    return Ok(current.clone());
+                }
+                Next::Building(building) if building == seed => true,
+                _ => builds.on_demand.iter().any(|building| building == seed),
+            };
+            if !waiting {
+                break;
+            }
+            drop(current);
+            builds = self.built.wait(builds).unwrap_or_else(|e| e.into_inner());
+        }
+        builds.on_demand.push(seed.to_vec());
+        drop(builds);
+
+        let dataset = self.build(seed).map(Arc::new);
+        let mut builds = lock(&self.builds);
+        if let Ok(dataset) = &dataset {
+            *lock(&self.current) = dataset.clone();
+        }
+        builds.on_demand.retain(|building| building != seed);
+        self.built.notify_all();
+        dataset
+    }
+
+    /// Starts building the dataset for `seed` in the background, unless it is
+    /// already built or under way. Until the switch both datasets are held in
+    /// memory.
+    pub fn prepare(self: &Arc<Self>, seed: &[u8]) {
+        let mut builds = lock(&self.builds);
+        match &builds.next {
+            Next::Building(building) if building == seed => return,
+            Next::Ready(dataset) if dataset.seed() == seed => return,
+            _ => {}
+        }
+        builds.next = Next::Building(seed.to_vec());
+        drop(builds);
+
+        tracing::info!("Preparing dataset for next seed {}", hex::encode(seed));
+        let shared = self.clone();
+        let seed = seed.to_vec();
+        thread::spawn(move || {
+            let dataset = shared.build(&seed);
+            let mut builds = lock(&shared.builds);
+            // A newer seed may have been announced in the meantime.
+            if matches!(&builds.next, Next::Building(building) if *building == seed) {
+                builds.next = match dataset {
+                    Ok(dataset) => Next::Ready(Arc::new(dataset)),
+                    Err(e) => {
+                        tracing::error!("Failed to prepare next dataset: {}", e);
+                        Next::None
+                    }
+                };
+            }
+            shared.built.notify_all();
+        });
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cpu::{self, CpuOptions},
+        selftest,
+    };
+
+    // An uneven split, so the last range takes a remainder.
+    #[test]
+    fn test_dataset_built_in_ranges() {
+        let flags = cpu::configure(&CpuOptions::default()) | RandomXFlag::FLAG_FULL_MEM;
+        let threads = NonZeroUsize::new(3).unwrap();
+        let dataset = Dataset::with_threads(flags, b"test key 001", threads).unwrap();
+        assert_eq!(
+            selftest::check(&dataset.vm().unwrap(), b"test key 001").unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_get_leaves_next_dataset_building() {
+        let flags = cpu::configure(&CpuOptions::default());
+        let shared = Arc::new(
+            SharedDataset::new(flags, b"current", NonZeroUsize::MIN, Stats::new(1)).unwrap(),
+        );
+        shared.prepare(b"next");
+        // Built on demand while the next one is under way.
+        assert_eq!(shared.get(b"other").unwrap().seed(), b"other");
+        match &lock(&shared.builds).next {
+            Next::Building(seed) => assert_eq!(seed, b"next"),
+            Next::Ready(dataset) => assert_eq!(dataset.seed(), b"next"),
+            Next::None => panic!("the next dataset was thrown away"),
+        }
+        assert_eq!(shared.get(b"next").unwrap().seed(), b"next");
+        assert!(matches!(lock(&shared.builds).next, Next::None));
+    }
+}
@@ -1,7 +1,5 @@
-
-use thiserror::Error;
 use std::sync::mpsc;
-
+use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -24,13 +22,65 @@ pub enum Error {
     Thread(String),
 
     #[error("Stratum error: {0}")]
-    Stratum(String),
+    Stratum(#[from] StratumError),
+
+    #[error("Daemon error: {0}")]
+    Daemon(#[from] DaemonError),
 
     #[error("Hex decode error: {0}")]
     HexDecode(#[from] hex::FromHexError),
 
     #[error("Configuration error: {0}")]
     Config(String),
+
+    /// RandomX computed a different hash than expected.
+    #[error("Hash verification failed: {0}")]
+    Verification(String),
+
+    /// Mining stopped, but not everything was wrapped up in time.
+    #[error("Unclean shutdown: {0}")]
+    Shutdown(String),
+}
+
+#[derive(Debug, Error)]
+pub enum StratumError {
+    /// The pool answered with an error object.
+    #[error("pool returned error {code}: {message}")]
+    Pool { code: i32, message: String },
+
+    /// The pool answered a request that needs a result with neither a result nor an error.
+    #[error("empty response to request {id}")]
+    EmptyResponse { id: u32 },
+
+    #[error("not connected to pool")]
+    NotConnected,
+
+    #[error("lost connection to pool")]
+    ConnectionLost,
+
+    #[error("TLS fingerprint mismatch: expected {expected}, got {actual}")]
+    FingerprintMismatch { expected: String, actual: String },
+
+    #[error("{0}")]
+    Protocol(String),
+}
+
+#[derive(Debug, Error)]
+pub enum DaemonError {
+    /// monerod answered with an error object.
+    #[error("daemon returned error {code}: {message}")]
+    Rpc { code: i32, message: String },
+
+    /// monerod answered with a status other than OK, e.g. while syncing.
+    #[error("daemon status {0}")]
+    Status(String),
+
+    #[error("HTTP request failed: {0}")]
+    Http(String),
+
+    /// monerod did not answer within the request timeout.
+    #[error("HTTP request timed out")]
+    Timeout,
 }
 
 impl<T> From<mpsc::SendError<T>> for Error {
@@ -63,6 +113,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::io_other_error)]
     fn test_io_error_conversion() {
         let io_err = std::io::Error::new(std::io::ErrorKind::Other, "fail");
         let our_err: Error = io_err.into();
@@ -110,6 +161,23 @@ mod tests {
         matches!(our_err, Error::Json(_));
     }
 
+    #[test]
+    fn test_stratum_error_conversion() {
+        let our_err: Error = StratumError::Pool {
+            code: -1,
+            message: "Unauthenticated".into(),
+        }
+        .into();
+        assert_eq!(
+            our_err.to_string(),
+            "Stratum error: pool returned error -1: Unauthenticated"
+        );
+        assert!(matches!(
+            our_err,
+            Error::Stratum(StratumError::Pool { code: -1, .. })
+        ));
+    }
+
     #[test]
     fn test_hex_decode_error_conversion() {
         let res = hex::decode("bad");
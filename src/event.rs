@@ -0,0 +1,57 @@
+use crate::{
+    control::Request,
+    job::Job,
+    share::{Share, ShareOutcome},
+    stratum::ConnectionState,
+    worker::ThreadState,
+};
+
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(test)]
+use std::time::{Duration, Instant};
+
+/// Something the mining loop has to act on.
+///
+/// The work source, the worker threads, the verifier and controllers all send theirs down
+/// one channel, so the loop sleeps in a single blocking receive until there is
+/// something to do.
+#[derive(Debug)]
+pub enum Event {
+    /// New work from the source.
+    Job(Job),
+    /// A worker thread found a share.
+    Share(Share),
+    /// The `Verifier`'s verdict on a found share. Only shares that passed
+    /// are submitted.
+    Verified(Share, bool),
+    /// The source's verdict on a submitted share.
+    Outcome(Share, ShareOutcome),
+    /// The source's connection changed. `Failed` ends the mining loop.
+    State(ConnectionState),
+    /// A mining thread with the given index stopped or resumed hashing.
+    Thread(usize, ThreadState),
+    /// Time to keep an idle connection open. The loop raises it itself when
+    /// the keepalive interval has passed.
+    Heartbeat,
+    /// A command from a `Controller`, `Command::Shutdown` included.
+    Command(Request),
+}
+
+/// Creates the channel the mining loop takes events from. Hand clones of the
+/// sender to the source, the worker and controllers.
+pub fn channel() -> (Sender<Event>, Receiver<Event>) {
+    mpsc::channel()
+}
+
+/// Waits up to `timeout` for the next share verdict, skipping other events.
+#[cfg(test)]
+pub(crate) fn next_outcome(events: &Receiver<Event>, timeout: Duration) -> (Share, ShareOutcome) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Event::Outcome(share, outcome)) => return (share, outcome),
+            Ok(_) => {}
+            Err(e) => panic!("no share outcome: {}", e),
+        }
+    }
+}
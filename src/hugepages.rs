@@ -0,0 +1,108 @@
+// RandomX memory sizes, see RandomX/src/configuration.h.
+const CACHE_SIZE: u64 = 256 * 1024 * 1024;
+const DATASET_SIZE: u64 = 2_147_483_648 + 33_554_368;
+const SCRATCHPAD_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Huge page counters from `/proc/meminfo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HugePages {
+    pub total: u64,
+    pub free: u64,
+    /// Page size in bytes.
+    pub page_size: u64,
+}
+
+impl HugePages {
+    /// Reads the current counters. Returns `None` where `/proc/meminfo` is
+    /// unavailable or huge pages are not supported.
+    pub fn read() -> Option<Self> {
+        Self::parse(&std::fs::read_to_string("/proc/meminfo").ok()?)
+    }
+
+    fn parse(meminfo: &str) -> Option<Self> {
+        let field = |name: &str| {
+            meminfo
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+                .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+        };
+        let page_size = field("Hugepagesize")? * 1024;
+        if page_size == 0 {
+            return None;
+        }
+        Some(Self {
+            total: field("HugePages_Total")?,
+            free: field("HugePages_Free")?,
+            page_size,
+        })
+    }
+
+    /// Pages needed to hold the cache, the dataset in full-memory mode, and
+    /// one scratchpad per thread.
+    pub fn required(&self, threads: usize, full_mem: bool) -> u64 {
+        let pages = |bytes: u64| bytes.div_ceil(self.page_size);
+        let mut needed = pages(CACHE_SIZE) + pages(SCRATCHPAD_SIZE) * threads as u64;
+        if full_mem {
+            needed += pages(DATASET_SIZE);
+        }
+        needed
+    }
+
+    /// Whether a VM scratchpad still fits into the free huge pages.
+    pub fn fits_scratchpad(&self) -> bool {
+        self.free * self.page_size >= SCRATCHPAD_SIZE
+    }
+}
+
+/// Logs how many huge pages are free compared to what mining needs.
+pub fn report(threads: usize, full_mem: bool) {
+    match HugePages::read() {
+        Some(pages) => {
+            let needed = pages.required(threads, full_mem);
+            tracing::info!(
+                "Huge pages: {} free of {} ({} KiB each), {} needed",
+                pages.free,
+                pages.total,
+                pages.page_size / 1024,
+                needed
+            );
+            if pages.free < needed {
+                tracing::warn!(
+                    "Not enough free huge pages, reserve more with: sysctl -w vm.nr_hugepages={}",
+                    pages.total + needed - pages.free
+                );
+            }
+        }
+        None => tracing::info!("Huge page availability unknown on this system"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMINFO: &str = "MemTotal:       32594660 kB
+HugePages_Total:    1280
+HugePages_Free:     1100
+HugePages_Rsvd:        0
+HugePages_Surp:        0
+Hugepagesize:       2048 kB
+";
+
+    #[test]
+    fn test_parse_meminfo() {
+        let pages = HugePages::parse(MEMINFO).unwrap();
+        assert_eq!(pages.total, 1280);
+        assert_eq!(pages.free, 1100);
+        assert_eq!(pages.page_size, 2 * 1024 * 1024);
+        assert!(pages.fits_scratchpad());
+        assert!(HugePages::parse("MemTotal: 1 kB\n").is_none());
+    }
+
+    #[test]
+    fn test_required_pages() {
+        let pages = HugePages::parse(MEMINFO).unwrap();
+        assert_eq!(pages.required(4, false), 128 + 4);
+        assert_eq!(pages.required(4, true), 128 + 4 + 1040);
+    }
+}
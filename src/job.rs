@@ -1,48 +1,35 @@
-use crate::share::Share;
+use crate::{
+    share::Share,
+    target::{Difficulty, Target},
+};
 use randomx_rs::RandomXVM;
 use serde::{Deserialize, Deserializer};
 
-// pub const THREAD_NONCE_START: u32 = 0;
-
-
-fn target_from_hex<'de, D>(deserializer: D) -> std::result::Result<u64, D::Error>
+pub(crate) fn optional_from_hex<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<u8>>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let hex: String = Deserialize::deserialize(deserializer)?;
-    let bytes = hex::decode(hex).map_err(serde::de::Error::custom)?;
-    if bytes.len() != 8 {
-        return Err(serde::de::Error::custom("Target must be 8 bytes"));
+    let hex: Option<String> = Deserialize::deserialize(deserializer)?;
+    match hex.as_deref() {
+        None | Some("") => Ok(None),
+        Some(hex) => hex::decode(hex).map(Some).map_err(serde::de::Error::custom),
     }
-    let arr: [u8; 8] = bytes
-        .try_into()
-        .map_err(|_| serde::de::Error::custom("Invalid target length"))?;
-    Ok(u64::from_le_bytes(arr))
 }
 
-// fn target_from_hex<'de, D>(deserializer: D) -> Result<u32, D::Error>
-
-// where
-
-//     D: Deserializer<'de>,
-
-// {
-
-//     let hex: String = Deserialize::deserialize(deserializer)?;
-
-//     let bytes = hex::decode(&hex).map_err(serde::de::Error::custom)?;
-
-//     if bytes.len() != 4 {
-
-//         return Err(serde::de::Error::custom("Target must be 4 bytes"));
-
-//     }
-
-//     Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
-
-// }
+/// Offset of the nonce in a block or hashing blob: it follows the major and
+/// minor version and timestamp varints and the 32-byte previous block id.
+pub(crate) fn nonce_offset(blob: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    for _ in 0..3 {
+        let len = blob.get(offset..)?.iter().position(|b| b & 0x80 == 0)? + 1;
+        offset += len;
+    }
+    offset += 32;
+    (blob.len() >= offset + 4).then_some(offset)
+}
 
-//
 #[derive(Debug, Clone, Deserialize)]
 pub struct Job {
     #[serde(rename = "job_id")]
@@ -51,58 +38,130 @@ pub struct Job {
     pub blob: Vec<u8>,
     #[serde(rename = "seed_hash", with = "hex")]
     pub seed: Vec<u8>,
-    #[serde(deserialize_with = "target_from_hex_vec")]
-    pub target: Vec<u8>,
+    pub target: Target,
+    /// Seed of the next RandomX epoch, announced by pools ahead of the switch.
+    #[serde(
+        rename = "next_seed_hash",
+        default,
+        deserialize_with = "optional_from_hex"
+    )]
+    pub next_seed: Option<Vec<u8>>,
+    /// Whether the job replaces the previous ones, making shares for them
+    /// stale. Monero pools rarely send the flag, as every job they send does.
+    #[serde(rename = "clean_jobs", default = "default_clean")]
+    pub clean: bool,
+}
+
+fn default_clean() -> bool {
+    true
+}
+
+/// A job at `difficulty` with a blob the nonce fits in, for tests.
+#[cfg(test)]
+pub(crate) fn test_job(id: &str, difficulty: u64) -> Job {
+    Job {
+        id: id.into(),
+        blob: vec![0x07; 76],
+        seed: vec![0; 32],
+        target: Target::from_difficulty(Difficulty(difficulty)),
+        next_seed: None,
+        clean: true,
+    }
 }
 
 impl Job {
-    pub fn difficulty(&self) -> u64 {
-        match self.target.len() {
-            4 => {
-                let mut arr = [0u8; 4];
-                arr.copy_from_slice(&self.target[..4]);
-                u32::from_le_bytes(arr) as u64
-            }
-            8 => {
-                let mut arr = [0u8; 8];
-                arr.copy_from_slice(&self.target[..8]);
-                u64::from_le_bytes(arr)
-            }
-            _ => u64::MAX,
-        }
+    pub fn difficulty(&self) -> Difficulty {
+        self.target.difficulty()
     }
 
-    pub fn next_share(&self, vm: &RandomXVM, nonce: u32, target: u64) -> Option<Share> {
-        if self.blob.len() < 43 {
-            tracing::warn!("Invalid blob length: {}", self.blob.len());
+    /// The blob with `nonce` in place, as it is hashed. The nonce goes where
+    /// `Daemon` puts it in the block it submits.
+    pub fn blob_with_nonce(&self, nonce: u32) -> Option<Vec<u8>> {
+        let Some(offset) = nonce_offset(&self.blob) else {
+            tracing::warn!("Invalid blob of {} bytes", self.blob.len());
             return None;
-        }
+        };
+        let mut blob = self.blob.clone();
+        blob[offset..offset + 4].copy_from_slice(&nonce.to_le_bytes());
+        Some(blob)
+    }
 
-        // Insert nonce into the blob
-        let mut blob_copy = self.blob.clone();
-        let nonce_bytes = nonce.to_le_bytes();
-        blob_copy[39..43].copy_from_slice(&nonce_bytes);
+    pub fn next_share(&self, vm: &RandomXVM, nonce: u32) -> Option<Share> {
+        let blob_copy = self.blob_with_nonce(nonce)?;
 
         // Calculate hash
         let hash = vm.calculate_hash(&blob_copy).ok()?;
         let hash_bytes = hash.as_slice();
 
-        // Compare hash against target (lower values are better)
-        let hash_val = u64::from_le_bytes(hash_bytes[24..32].try_into().unwrap());
-
-        if nonce % 100_000 == 0 {
+        if nonce.is_multiple_of(100_000) {
             tracing::debug!(
-                "Nonce: {}, Hash val: {}, Target: {}",
+                "Nonce: {}, Hash: {}, Difficulty: {}",
                 nonce,
-                hash_val,
-                hex::encode(&self.target)
+                hex::encode(hash_bytes),
+                self.difficulty()
             );
         }
 
-        if hash_val <= target {
+        // Compare the whole hash against the target (lower values are better)
+        if self.target.is_met_by(hash_bytes) {
             Some(Share::new(self.id.clone(), nonce, hash))
         } else {
             None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonce_offset() {
+        // A one byte timestamp puts the nonce at 35, the usual five bytes at 39.
+        let mut blob = vec![0x10, 0x10, 0x06];
+        blob.extend([0xaa; 36]);
+        assert_eq!(nonce_offset(&blob), Some(35));
+        let mut blob = hex::decode("1010a0b1c2d306").unwrap();
+        blob.extend([0xaa; 40]);
+        assert_eq!(nonce_offset(&blob), Some(39));
+        assert_eq!(nonce_offset(&[0x10, 0x10, 0x80]), None);
+        assert_eq!(nonce_offset(&blob[..42]), None);
+    }
+
+    #[test]
+    fn test_blob_with_nonce() {
+        let mut blob = vec![0x10, 0x10, 0x06];
+        blob.extend([0xaa; 40]);
+        let job = Job {
+            blob,
+            ..test_job("1", 1)
+        };
+        let blob = job.blob_with_nonce(0x11223344).unwrap();
+        assert_eq!(blob[35..39], [0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(blob[39..], [0xaa; 4]);
+    }
+
+    #[test]
+    fn test_parse_next_seed() {
+        let job: Job = serde_json::from_str(
+            r#"{"job_id":"1","blob":"0707","seed_hash":"aa","next_seed_hash":"bb","target":"b88d0600"}"#,
+        )
+        .unwrap();
+        assert_eq!(job.next_seed, Some(vec![0xbb]));
+        assert!(job.clean);
+        assert_eq!(job.difficulty(), Difficulty(10000));
+
+        let job: Job = serde_json::from_str(
+            r#"{"job_id":"1","blob":"0707","seed_hash":"aa","next_seed_hash":"","target":"b88d0600"}"#,
+        )
+        .unwrap();
+        assert_eq!(job.next_seed, None);
+
+        let job: Job = serde_json::from_str(
+            r#"{"job_id":"1","blob":"0707","seed_hash":"aa","target":"b88d0600","clean_jobs":false}"#,
+        )
+        .unwrap();
+        assert_eq!(job.next_seed, None);
+        assert!(!job.clean);
+    }
+}
@@ -1,13 +1,34 @@
 extern crate core;
+pub mod address;
+pub mod api;
+pub mod bench;
+pub mod config;
+pub mod control;
+pub mod cpu;
+pub mod daemon;
+pub mod dataset;
 pub mod error;
+pub mod event;
+pub mod hugepages;
 pub mod job;
+pub mod miner;
+pub mod selftest;
 pub mod share;
+pub mod source;
+pub mod stats;
 pub mod stratum;
+pub mod target;
+pub mod tracker;
+pub mod verifier;
 pub mod worker;
 
 // Re-export main types for easy access
-pub use error::{Error, Result};
+pub use daemon::Daemon;
+pub use error::{DaemonError, Error, Result, StratumError};
+pub use event::Event;
 pub use job::Job;
-pub use share::Share;
+pub use share::{Share, ShareOutcome};
+pub use source::{Polled, PullSource, WorkSource};
 pub use stratum::Stratum;
+pub use target::{Difficulty, Target};
 pub use worker::Worker;
@@ -1,86 +1,423 @@
-use clap::Parser;
-use obfstr::obfstr;
+use clap::{builder::BoolishValueParser, Parser, Subcommand};
+use randomx_rs::RandomXFlag;
 
-
-use orng_rust::{Error, Result, Stratum, Worker};
+use orng_rust::{
+    api,
+    bench::{self, Limit},
+    config::{Config, PoolConfig},
+    control::{Command, Controller},
+    cpu::{self, Argon2Impl, CpuOptions},
+    event, miner, selftest,
+    stats::Stats,
+    stratum::{Pool, ReconnectPolicy, TlsConfig},
+    tracker::StalePolicy,
+    verifier::Verifier,
+    Daemon, Error, Result, Stratum, WorkSource, Worker,
+};
 use std::{
+    net::{IpAddr, SocketAddr},
     num::NonZeroUsize,
-    time::{Duration, Instant},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
 };
 
-const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
-
 #[derive(Parser)]
 struct Args {
-    #[arg(short = 'o', long, default_value = "pool.hashvault.pro:443")]
-    url: String,
+    #[command(subcommand)]
+    command: Option<Cmd>,
+    /// Read settings from this TOML or JSON file; flags and environment variables override it
+    #[arg(short, long, global = true, env = "ORNG_CONFIG")]
+    config: Option<PathBuf>,
+    /// Print the resolved configuration as TOML and exit
+    #[arg(long)]
+    dump_config: bool,
+    /// Pool URL, repeat for backup pools in order of priority
+    #[arg(short = 'o', long, env = "ORNG_URL", value_delimiter = ',')]
+    url: Vec<String>,
+    /// Wallet address for the pool given at the same position, the last one is reused; with --daemon, where blocks pay out to
+    #[arg(short, long, env = "ORNG_USER", value_delimiter = ',')]
+    user: Vec<String>,
+    /// Password for the pool given at the same position, the last one is reused
+    #[arg(short, long, env = "ORNG_PASS", value_delimiter = ',')]
+    pass: Vec<String>,
+    /// Mine solo against this monerod RPC endpoint instead of a pool, e.g. http://127.0.0.1:18081
+    #[arg(long, env = "ORNG_DAEMON")]
+    daemon: Option<String>,
+    /// Worker threads [default: all cores]
+    #[arg(short, long, global = true, env = "ORNG_THREADS")]
+    threads: Option<NonZeroUsize>,
+    /// Pin worker threads to these cores in turn, e.g. 0,2,4,6 [default: all cores]
+    #[arg(long, global = true, env = "ORNG_AFFINITY", value_delimiter = ',')]
+    affinity: Vec<usize>,
     #[arg(
-        short,
         long,
-        default_value = "44qARb3o5kWimeStvm9g4r5kTCMSZio8SEWDcEy9HKnnXg6iQns7Mqi4SrrSNZV6mG1YQWqRgr5Lph1BxfQFK8Kz8hMidXR"
+        global = true,
+        env = "ORNG_LIGHT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new()
     )]
-    user: String,
-    #[arg(short, long, default_value = "x")]
-    pass: String,
-    #[arg(short, long, default_value_t = all_threads())]
-    threads: NonZeroUsize,
-    #[arg(long)]
-    light: bool,
+    light: Option<bool>,
+    /// Allocate the RandomX cache, dataset and scratchpads in huge pages
+    #[arg(
+        long,
+        global = true,
+        env = "ORNG_HUGE_PAGES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new()
+    )]
+    huge_pages: Option<bool>,
+    /// Use the RandomX interpreter even where JIT compilation is available
+    #[arg(
+        long,
+        global = true,
+        env = "ORNG_NO_JIT",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new()
+    )]
+    no_jit: Option<bool>,
+    /// Use software AES even where the CPU supports AES-NI
+    #[arg(
+        long,
+        global = true,
+        env = "ORNG_SOFT_AES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new()
+    )]
+    soft_aes: Option<bool>,
+    /// Argon2 implementation for filling the RandomX cache [default: auto]
+    #[arg(long, global = true, env = "ORNG_ARGON2_IMPL", value_enum)]
+    argon2_impl: Option<Argon2Impl>,
+    /// Recompute shares with the RandomX interpreter and drop those that do not verify
+    #[arg(
+        long,
+        env = "ORNG_VERIFY_SHARES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new()
+    )]
+    verify_shares: Option<bool>,
+    /// Log an error when more than this percentage of recently verified shares are invalid [default: 5]
+    #[arg(long, env = "ORNG_INVALID_ALERT")]
+    invalid_alert: Option<f64>,
+    /// What to do with shares found for a job the pool has replaced [default: drop]
+    #[arg(long, env = "ORNG_STALE_POLICY", value_enum)]
+    stale_policy: Option<StalePolicy>,
+    /// How long after a job was replaced its shares are still submitted under --stale-policy grace [default: 2000]
+    #[arg(long, env = "ORNG_STALE_GRACE_MS")]
+    stale_grace_ms: Option<u64>,
+    /// Accept self-signed or otherwise untrusted pool certificates
+    #[arg(
+        long,
+        env = "ORNG_TLS_INSECURE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new()
+    )]
+    tls_insecure: Option<bool>,
+    /// Pin the pool certificate to this SHA-256 fingerprint (hex)
+    #[arg(long, env = "ORNG_TLS_FINGERPRINT")]
+    tls_fingerprint: Option<String>,
+    /// Give up after this many consecutive failed reconnect attempts (default: retry forever)
+    #[arg(long, env = "ORNG_RETRIES")]
+    retries: Option<u32>,
+    /// Seconds between attempts to switch back to a higher-priority pool [default: 120]
+    #[arg(long, env = "ORNG_PROBE_INTERVAL")]
+    probe_interval: Option<u64>,
+    /// Serve a JSON summary on this port
+    #[arg(long, env = "ORNG_HTTP_PORT")]
+    http_port: Option<u16>,
+    /// Address the HTTP API listens on [default: 127.0.0.1]
+    #[arg(long, env = "ORNG_HTTP_HOST")]
+    http_host: Option<IpAddr>,
+    /// Require this bearer token for HTTP API requests; also enables the control API
+    #[arg(long, env = "ORNG_HTTP_TOKEN")]
+    http_token: Option<String>,
+    /// error, warn, info, debug or trace [default: debug]
+    #[arg(long, global = true, env = "ORNG_LOG_LEVEL")]
+    log_level: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Cmd {
+    /// Mine a synthetic job offline and report the hashrate
+    Bench {
+        /// Run for this many seconds
+        #[arg(long, default_value_t = 20, conflicts_with = "hashes")]
+        seconds: u64,
+        /// Run until this many hashes are done instead
+        #[arg(long)]
+        hashes: Option<u64>,
+        /// Check the RandomX reference test vectors on the benchmarked dataset first
+        #[arg(long)]
+        verify: bool,
+    },
+    /// Check the RandomX reference test vectors in light and full mode
+    Selftest,
 }
 
-fn all_threads() -> NonZeroUsize {
-    std::thread::available_parallelism().unwrap()
+impl Args {
+    // Layers the flags and environment variables clap collected over the
+    // config file, or the defaults without one.
+    fn resolve(self) -> Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        if !self.url.is_empty() {
+            // Logins not given on the command line come from the file.
+            let pools = std::mem::take(&mut config.pools);
+            config.pools = self
+                .url
+                .into_iter()
+                .enumerate()
+                .map(|(i, url)| {
+                    let base = pools.get(i).or(pools.last());
+                    PoolConfig {
+                        url,
+                        user: base.map_or_else(String::new, |p| p.user.clone()),
+                        pass: base.map_or_else(|| "x".into(), |p| p.pass.clone()),
+                    }
+                })
+                .collect();
+        }
+        for (i, pool) in config.pools.iter_mut().enumerate() {
+            if let Some(user) = self.user.get(i).or(self.user.last()) {
+                pool.user = user.clone();
+            }
+            if let Some(pass) = self.pass.get(i).or(self.pass.last()) {
+                pool.pass = pass.clone();
+            }
+        }
+        if !self.affinity.is_empty() {
+            config.affinity = Some(self.affinity);
+        }
+        config.daemon = self.daemon.or(config.daemon);
+        config.wallet = self.user.first().cloned().or(config.wallet);
+        config.threads = self.threads.unwrap_or(config.threads);
+        config.light = self.light.unwrap_or(config.light);
+        config.huge_pages = self.huge_pages.unwrap_or(config.huge_pages);
+        config.no_jit = self.no_jit.unwrap_or(config.no_jit);
+        config.soft_aes = self.soft_aes.unwrap_or(config.soft_aes);
+        config.argon2_impl = self.argon2_impl.unwrap_or(config.argon2_impl);
+        config.verify_shares = self.verify_shares.unwrap_or(config.verify_shares);
+        config.invalid_alert = self.invalid_alert.unwrap_or(config.invalid_alert);
+        config.stale_policy = self.stale_policy.unwrap_or(config.stale_policy);
+        config.stale_grace_ms = self.stale_grace_ms.unwrap_or(config.stale_grace_ms);
+        config.tls_insecure = self.tls_insecure.unwrap_or(config.tls_insecure);
+        config.tls_fingerprint = self.tls_fingerprint.or(config.tls_fingerprint);
+        config.retries = self.retries.or(config.retries);
+        config.probe_interval = self.probe_interval.unwrap_or(config.probe_interval);
+        config.http.port = self.http_port.or(config.http.port);
+        config.http.host = self.http_host.unwrap_or(config.http.host);
+        config.http.token = self.http_token.or(config.http.token);
+        config.log_level = self.log_level.unwrap_or(config.log_level);
+        Ok(config)
+    }
 }
 
 fn run() -> Result<()> {
+    let mut args = Args::parse();
+    let command = args.command.take();
+    let dump_config = args.dump_config;
+    let config = args.resolve()?;
+    let log_level = config.log_level()?;
+    if dump_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
     tracing_subscriber::fmt()
         .pretty()
-        .with_max_level(tracing::Level::DEBUG)
+        .with_max_level(log_level)
         .with_file(false)
         .with_line_number(false)
         .init();
-
-    let Args {
-        url,
-        user,
-        pass,
-        light,
-        threads,
-    } = Args::parse();
-
-    let mut stratum = Stratum::login(&url, &user, &pass)?;
-    let first_job = loop {
-        match stratum.try_recv_job() {
-            Ok(job) => break job,
-            Err(Error::Channel(ref msg)) if msg == "Channel is empty" => {
-                std::thread::sleep(std::time::Duration::from_millis(100));
-                continue;
-            }
-            Err(e) => return Err(Error::Stratum(format!("Failed to get first job: {}", e))),
-        }
+    let cpu = CpuOptions {
+        no_jit: config.no_jit,
+        soft_aes: config.soft_aes,
+        argon2: config.argon2_impl,
+        affinity: config.affinity.clone(),
     };
-    let worker = Worker::init(first_job, threads, light)?;
-    let mut timer = Instant::now();
 
-    loop {
-        if let Ok(job) = stratum.try_recv_job() {
-            worker.update_job(job);
+    match command {
+        Some(Cmd::Bench {
+            seconds,
+            hashes,
+            verify,
+        }) => {
+            let limit = match hashes {
+                Some(hashes) => Limit::Hashes(hashes),
+                None => Limit::Time(Duration::from_secs(seconds)),
+            };
+            let report = bench::run(
+                config.threads,
+                config.light,
+                config.huge_pages,
+                cpu,
+                limit,
+                verify,
+            )?;
+            println!("{}", report);
+            return Ok(());
         }
-
-        if let Some(share) = worker.try_recv_share() {
-            stratum.submit(share)?;
+        Some(Cmd::Selftest) => {
+            let mut flags = cpu::configure(&cpu);
+            if config.huge_pages {
+                flags |= RandomXFlag::FLAG_LARGE_PAGES;
+            }
+            println!("{}", selftest::run(flags, &selftest::keys())?);
+            // --light skips the 2 GiB dataset, for hosts that only mine in
+            // light mode.
+            if !config.light {
+                let flags = flags | RandomXFlag::FLAG_FULL_MEM;
+                println!("{}", selftest::run(flags, &selftest::keys())?);
+            }
+            return Ok(());
         }
+        None => config.validate()?,
+    }
 
-        if timer.elapsed() >= KEEP_ALIVE_INTERVAL {
-            stratum.keep_alive()?;
-            timer = Instant::now();
+    // The first SIGINT or SIGTERM shuts down once mining has started, the
+    // second exits right away.
+    let (events, event_rx) = event::channel();
+    let controller = Controller::new(events.clone());
+    let signals = controller.clone();
+    let stopping = AtomicBool::new(false);
+    ctrlc::set_handler(move || {
+        if stopping.swap(true, Ordering::Relaxed) {
+            tracing::warn!("Exiting without shutting down");
+            std::process::exit(130);
         }
+        tracing::info!("Stop requested, shutting down (repeat to exit immediately)");
+        let _ = signals.post(Command::Shutdown);
+    })
+    .map_err(|e| Error::Thread(format!("Failed to install signal handler: {}", e)))?;
+
+    let tls = TlsConfig {
+        insecure: config.tls_insecure,
+        fingerprint: config.tls_fingerprint,
+    };
+    let reconnect = ReconnectPolicy {
+        max_attempts: config.retries,
+        probe_interval: Duration::from_secs(config.probe_interval),
+        ..Default::default()
+    };
+    let pools = config
+        .pools
+        .iter()
+        .map(|pool| Pool::new(&pool.url, &pool.user, &pool.pass, tls.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    let mut source: Box<dyn WorkSource> = match &config.daemon {
+        Some(daemon) => Box::new(Daemon::connect(
+            daemon,
+            config.wallet.as_deref().unwrap_or_default(),
+            reconnect,
+            events.clone(),
+        )?),
+        None => Box::new(Stratum::login(pools, reconnect, events.clone())?),
+    };
+    let first_job = miner::first_job(&event_rx)?;
+    let threads = config.threads;
+    let stats = Stats::new(threads.get());
+    let verifier = config.verify_shares.then(|| {
+        let verifier = Verifier::new(&cpu, config.invalid_alert, stats.clone(), events.clone());
+        verifier.update_job(&first_job);
+        verifier
+    });
+    if let Some(port) = config.http.port {
+        api::serve(
+            SocketAddr::new(config.http.host, port),
+            config.http.token,
+            stats.clone(),
+            Some(controller),
+        )?;
     }
+    let worker = Worker::init(
+        first_job,
+        threads,
+        config.light,
+        config.huge_pages,
+        cpu,
+        stats.clone(),
+        events,
+    )?;
+    worker.set_stale_policy(
+        config.stale_policy,
+        Duration::from_millis(config.stale_grace_ms),
+    );
+    miner::run(source.as_mut(), &worker, &stats, &event_rx, verifier)
 }
 
 fn main() {
     if let Err(e) = run() {
-        tracing::error!("Application error: {}", e);
+        if tracing::dispatcher::has_been_set() {
+            tracing::error!("Application error: {}", e);
+        } else {
+            eprintln!("Application error: {}", e);
+        }
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    // Resolves `args` as if the environment held only `env`. The variables
+    // become defaults, which rank below flags just like clap's env lookup.
+    fn resolve_with_env(args: &[&str], env: &[(&str, &'static str)]) -> Config {
+        let command = Args::command().mut_args(|arg| {
+            let Some(var) = arg.get_env() else {
+                return arg;
+            };
+            match env.iter().find(|(name, _)| var == *name) {
+                Some((_, value)) => arg.env(None).default_value(*value),
+                None => arg.env(None),
+            }
+        });
+        let matches = command
+            .try_get_matches_from([&["orng-rust"], args].concat())
+            .unwrap();
+        Args::from_arg_matches(&matches).unwrap().resolve().unwrap()
+    }
+
+    fn resolve(args: &[&str]) -> Config {
+        resolve_with_env(args, &[])
+    }
+
+    #[test]
+    fn test_switches_override_in_both_directions() {
+        let path = std::env::temp_dir().join(format!("orng-{}-switches.toml", std::process::id()));
+        std::fs::write(&path, "light = true\ntls_insecure = true\n").unwrap();
+        let file = path.to_str().unwrap();
+
+        let config = resolve(&["-c", file]);
+        assert!(config.light && config.tls_insecure);
+        let config = resolve(&["-c", file, "--light=false", "--tls-insecure=no"]);
+        assert!(!config.light && !config.tls_insecure);
+        let config = resolve(&["--light", "--verify-shares", "bench"]);
+        assert!(config.light && config.verify_shares);
+        assert!(!resolve(&[]).light);
+
+        let env = [("ORNG_LIGHT", "false")];
+        assert!(!resolve_with_env(&["-c", file], &env).light);
+        // The command line beats the environment.
+        assert!(resolve_with_env(&["-c", file, "--light"], &env).light);
+        let env = [("ORNG_LIGHT", "1")];
+        assert!(resolve_with_env(&[], &env).light);
+        assert!(!resolve_with_env(&["--light=false"], &env).light);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,581 @@
+use crate::{
+    control::Command,
+    error::{Error, Result, StratumError},
+    event::Event,
+    job::Job,
+    share::{Share, ShareOutcome},
+    source::WorkSource,
+    stats::Stats,
+    stratum::ConnectionState,
+    verifier::Verifier,
+    worker::Worker,
+};
+
+use std::{
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+// How long shutdown waits for the source to answer outstanding shares.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// Waits on `events` for the source's first job, which the worker needs to
+/// start. A `Command::Shutdown` before it gives up, other commands are
+/// refused.
+pub fn first_job(events: &Receiver<Event>) -> Result<Job> {
+    loop {
+        match events.recv()? {
+            Event::Job(job) => return Ok(job),
+            Event::State(ConnectionState::Failed) => {
+                return Err(StratumError::Protocol("Failed to get first job".into()).into())
+            }
+            Event::Command(request) if request.command == Command::Shutdown => {
+                let _ = request.reply.send(Ok(()));
+                return Err(Error::Shutdown("stopped before the first job".into()));
+            }
+            Event::Command(request) => {
+                let _ = request.reply.send(Err(Error::Config(format!(
+                    "Cannot carry out {:?} before the first job",
+                    request.command
+                ))));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Feeds jobs from `source` to `worker` and found shares back, until the
+/// source gives up or a `Command::Shutdown` arrives. Everything it acts on
+/// comes in on `events`, which the source, the worker and controllers send
+/// to, so it sleeps while none of them has anything new. Share outcomes and
+/// connection changes are recorded in `stats`. With a `verifier`, shares that
+/// do not verify are dropped instead of submitted.
+///
+/// On shutdown the worker is stopped, outstanding shares get a few seconds to
+/// be answered, and the source is closed. Shares left unanswered make it an
+/// `Error::Shutdown`.
+pub fn run<S: WorkSource + ?Sized>(
+    source: &mut S,
+    worker: &Worker,
+    stats: &Stats,
+    events: &Receiver<Event>,
+    verifier: Option<Verifier>,
+) -> Result<()> {
+    let mut heartbeat = Instant::now() + HEARTBEAT_INTERVAL;
+    // Shares submitted but not answered yet.
+    let mut pending = 0;
+
+    loop {
+        // A busy channel must not hold the keepalive off.
+        let event = match heartbeat.checked_duration_since(Instant::now()) {
+            Some(wait) if !wait.is_zero() => match events.recv_timeout(wait) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => Event::Heartbeat,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(Error::Channel("Every event sender is gone".into()))
+                }
+            },
+            _ => Event::Heartbeat,
+        };
+
+        match event {
+            Event::Job(job) => {
+                if let Some(verifier) = &verifier {
+                    verifier.update_job(&job);
+                }
+                worker.update_job(job);
+            }
+            Event::Share(share) => {
+                if worker.accept_share(&share) {
+                    hand_on(source, &verifier, share, &mut pending);
+                }
+            }
+            Event::Verified(share, valid) => {
+                pending = pending.saturating_sub(1);
+                if valid {
+                    submit(source, share, &mut pending);
+                }
+            }
+            Event::Outcome(share, outcome) => {
+                pending = pending.saturating_sub(1);
+                record_outcome(stats, &share, outcome);
+            }
+            Event::State(state) => {
+                stats.record_state(source.endpoint(), state);
+                match state {
+                    ConnectionState::Connected => {
+                        tracing::info!("Connected to {}", source.endpoint())
+                    }
+                    ConnectionState::Reconnecting { .. } => {}
+                    ConnectionState::Failed => return Err(StratumError::ConnectionLost.into()),
+                }
+            }
+            Event::Thread(index, state) => stats.record_thread_state(index, &state),
+            Event::Heartbeat => {
+                if let Err(e) = source.heartbeat() {
+                    tracing::warn!("Failed to send keepalive: {}", e);
+                }
+                heartbeat = Instant::now() + HEARTBEAT_INTERVAL;
+            }
+            Event::Command(request) if request.command == Command::Shutdown => {
+                let _ = request.reply.send(Ok(()));
+                return shutdown(source, worker, stats, events, &verifier, pending);
+            }
+            Event::Command(request) => {
+                let result = apply(request.command, source, worker);
+                if let Err(e) = &result {
+                    tracing::warn!("{:?} failed: {}", request.command, e);
+                }
+                let _ = request.reply.send(result);
+            }
+        }
+    }
+}
+
+// Hands a share a worker found to the verifier if there is one, and otherwise
+// submits it. Either way an answer is pending until it comes back.
+fn hand_on<S: WorkSource + ?Sized>(
+    source: &mut S,
+    verifier: &Option<Verifier>,
+    share: Share,
+    pending: &mut usize,
+) {
+    match verifier {
+        Some(verifier) => {
+            verifier.check(share);
+            *pending += 1;
+        }
+        None => submit(source, share, pending),
+    }
+}
+
+// Submits `share`, counting it as pending if the source reports outcomes.
+fn submit<S: WorkSource + ?Sized>(source: &mut S, share: Share, pending: &mut usize) {
+    match source.submit(share) {
+        Ok(()) if source.reports_outcomes() => *pending += 1,
+        Ok(()) => {}
+        Err(e) => tracing::warn!("Failed to submit share: {}", e),
+    }
+}
+
+fn record_outcome(stats: &Stats, share: &Share, outcome: ShareOutcome) {
+    stats.record_outcome(&outcome);
+    match outcome {
+        ShareOutcome::Accepted => {}
+        ShareOutcome::Rejected { code, message } => {
+            tracing::warn!(
+                "Share for job {} rejected ({}): {}",
+                share.job_id,
+                code,
+                message
+            )
+        }
+        ShareOutcome::Stale => tracing::warn!("Share for job {} was stale", share.job_id),
+        ShareOutcome::Timeout => {
+            tracing::warn!("Share for job {} timed out", share.job_id)
+        }
+    }
+}
+
+fn shutdown<S: WorkSource + ?Sized>(
+    source: &mut S,
+    worker: &Worker,
+    stats: &Stats,
+    events: &Receiver<Event>,
+    verifier: &Option<Verifier>,
+    mut pending: usize,
+) -> Result<()> {
+    tracing::info!("Shutting down");
+    // Once the threads are stopped every share they found is queued, so the
+    // loop below submits them all before it waits for answers.
+    worker.stop();
+    let deadline = Instant::now() + SHUTDOWN_GRACE;
+    loop {
+        let event = match events.try_recv() {
+            Ok(event) => event,
+            Err(_) if pending == 0 => break,
+            Err(_) => match events.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            {
+                Ok(event) => event,
+                Err(_) => break,
+            },
+        };
+        match event {
+            Event::Share(share) => {
+                if worker.accept_share(&share) {
+                    hand_on(source, verifier, share, &mut pending);
+                }
+            }
+            Event::Verified(share, valid) => {
+                pending = pending.saturating_sub(1);
+                if valid {
+                    submit(source, share, &mut pending);
+                }
+            }
+            Event::Outcome(share, outcome) => {
+                pending = pending.saturating_sub(1);
+                record_outcome(stats, &share, outcome);
+            }
+            // Dropping a command's reply tells its controller it was not
+            // carried out.
+            Event::Job(_)
+            | Event::State(_)
+            | Event::Thread(..)
+            | Event::Heartbeat
+            | Event::Command(_) => {}
+        }
+    }
+
+    let shares = stats.shares();
+    let uptime = stats.uptime();
+    tracing::info!(
+        "Mined for {}s: {} hashes, {:.2} H/s average, {:.2} H/s highest; \
+         {} shares accepted, {} rejected, {} stale, {} timed out",
+        uptime.as_secs(),
+        stats.hashes_total(),
+        stats.hashes_total() as f64 / uptime.as_secs_f64(),
+        stats.highest_hashrate().unwrap_or_default(),
+        shares.accepted,
+        shares.rejected,
+        shares.stale,
+        shares.timeout
+    );
+    let verified = stats.verification();
+    if verified.checked > 0 {
+        tracing::info!(
+            "Verified {} shares locally, {} invalid",
+            verified.checked,
+            verified.invalid
+        );
+    }
+    source.close();
+    if pending > 0 {
+        return Err(Error::Shutdown(format!(
+            "{} shares were not answered",
+            pending
+        )));
+    }
+    tracing::info!("Shut down cleanly");
+    Ok(())
+}
+
+fn apply<S: WorkSource + ?Sized>(command: Command, source: &mut S, worker: &Worker) -> Result<()> {
+    match command {
+        Command::Pause => {
+            worker.pause();
+            tracing::info!("Paused");
+        }
+        Command::Resume => {
+            worker.resume();
+            tracing::info!("Resumed");
+        }
+        Command::SetThreads { threads } => {
+            worker.set_threads(threads);
+            tracing::info!("Mining on {} threads", threads);
+        }
+        Command::SwitchPool { index } => source.switch_pool(index)?,
+        // Ends the loop, so `run` handles it itself.
+        Command::Shutdown => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        control::Controller,
+        cpu::CpuOptions,
+        event,
+        job::test_job,
+        stratum::{
+            mock::{self, wait_until, MockPool},
+            Pool, ReconnectPolicy, Stratum, TlsConfig,
+        },
+    };
+    use std::{
+        num::NonZeroUsize,
+        sync::{mpsc::Sender, Arc},
+        thread,
+    };
+
+    // Refuses to switch pools, and fails once it has both been asked to and
+    // been handed a share. Accepts shares if `answer`, and otherwise reports
+    // no outcomes at all.
+    struct FakeSource {
+        events: Sender<Event>,
+        shares: Vec<Share>,
+        switches: Vec<usize>,
+        answer: bool,
+        closed: bool,
+    }
+
+    impl FakeSource {
+        fn fail_when_done(&self) {
+            if !self.shares.is_empty() && !self.switches.is_empty() {
+                let _ = self.events.send(Event::State(ConnectionState::Failed));
+            }
+        }
+    }
+
+    impl WorkSource for FakeSource {
+        fn submit(&mut self, share: Share) -> Result<()> {
+            if self.answer {
+                let outcome = Event::Outcome(share.clone(), ShareOutcome::Accepted);
+                let _ = self.events.send(outcome);
+            }
+            self.shares.push(share);
+            self.fail_when_done();
+            Ok(())
+        }
+
+        fn heartbeat(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn reports_outcomes(&self) -> bool {
+            self.answer
+        }
+
+        fn endpoint(&self) -> String {
+            "fake".into()
+        }
+
+        fn switch_pool(&mut self, index: usize) -> Result<()> {
+            self.switches.push(index);
+            self.fail_when_done();
+            Err(Error::Config("single pool".into()))
+        }
+
+        fn close(&mut self) {
+            self.closed = true;
+        }
+    }
+
+    // A fake source that has sent a single job at `difficulty`, and a worker
+    // mining it. At difficulty 1 every hash is a share.
+    fn start(answer: bool, difficulty: u64) -> (FakeSource, Receiver<Event>, Arc<Stats>, Worker) {
+        let (events, event_rx) = event::channel();
+        events
+            .send(Event::Job(test_job("fake", difficulty)))
+            .unwrap();
+        let job = first_job(&event_rx).unwrap();
+        let stats = Stats::new(1);
+        let worker = Worker::init(
+            job,
+            NonZeroUsize::new(1).unwrap(),
+            true,
+            false,
+            CpuOptions::default(),
+            stats.clone(),
+            events.clone(),
+        )
+        .unwrap();
+        let source = FakeSource {
+            events,
+            shares: Vec::new(),
+            switches: Vec::new(),
+            answer,
+            closed: false,
+        };
+        (source, event_rx, stats, worker)
+    }
+
+    #[test]
+    fn test_run_with_fake_source() {
+        let (mut source, events, stats, worker) = start(false, 1);
+
+        let controller = Controller::new(source.events.clone());
+        let result = thread::scope(|scope| {
+            scope.spawn(|| {
+                controller.send(Command::Pause).unwrap();
+                controller
+                    .send(Command::SetThreads {
+                        threads: NonZeroUsize::new(2).unwrap(),
+                    })
+                    .unwrap();
+                controller.send(Command::Resume).unwrap();
+                assert!(controller.send(Command::SwitchPool { index: 1 }).is_err());
+            });
+            run(&mut source, &worker, &stats, &events, None)
+        });
+        assert!(matches!(
+            result,
+            Err(Error::Stratum(StratumError::ConnectionLost))
+        ));
+        assert_eq!(source.shares[0].job_id, "fake");
+        assert_eq!(source.switches, [1]);
+        assert_eq!(stats.job().unwrap().id, "fake");
+        assert!(stats.hashes_total() > 0);
+        assert_eq!(worker.threads(), 2);
+        assert_eq!(stats.threads(), 2);
+        assert!(!worker.is_paused());
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let (mut source, events, stats, worker) = start(true, 1);
+
+        let controller = Controller::new(source.events.clone());
+        let result = thread::scope(|scope| {
+            scope.spawn(|| {
+                while stats.shares().accepted == 0 {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                controller.post(Command::Shutdown).unwrap();
+            });
+            run(&mut source, &worker, &stats, &events, None)
+        });
+        assert!(result.is_ok());
+        assert!(source.closed);
+        assert_eq!(worker.threads(), 0);
+        // Every share found, including those still queued at shutdown, was
+        // submitted and answered.
+        assert_eq!(stats.shares().accepted, source.shares.len() as u64);
+    }
+
+    #[test]
+    fn test_shutdown_without_outcomes() {
+        let (mut source, events, stats, worker) = start(false, 1);
+
+        let controller = Controller::new(source.events.clone());
+        let started = Instant::now();
+        let result = thread::scope(|scope| {
+            scope.spawn(|| {
+                while stats.hashes_total() < 2 {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                controller.post(Command::Shutdown).unwrap();
+            });
+            run(&mut source, &worker, &stats, &events, None)
+        });
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(!source.shares.is_empty());
+        // Nothing was left to wait for.
+        assert!(started.elapsed() < SHUTDOWN_GRACE);
+    }
+
+    #[test]
+    fn test_shutdown_before_first_job() {
+        let (events, event_rx) = event::channel();
+        let controller = Controller::new(events);
+        let handle = thread::spawn(move || {
+            assert!(controller.send(Command::Pause).is_err());
+            controller.send(Command::Shutdown).unwrap();
+        });
+        assert!(matches!(first_job(&event_rx), Err(Error::Shutdown(_))));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_unverified_shares_are_not_submitted() {
+        // Every hash meeting the job's target would be a share, and the
+        // interpreter cannot keep up with that many.
+        let (mut source, events, stats, worker) = start(false, 16);
+        // The verifier holds the job to a target no hash meets.
+        let verifier = Verifier::new(
+            &CpuOptions::default(),
+            5.0,
+            stats.clone(),
+            source.events.clone(),
+        );
+        verifier.update_job(&test_job("fake", u64::MAX));
+
+        let controller = Controller::new(source.events.clone());
+        let result = thread::scope(|scope| {
+            scope.spawn(|| {
+                while stats.verification().invalid < 2 {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                controller.post(Command::Shutdown).unwrap();
+            });
+            run(&mut source, &worker, &stats, &events, Some(verifier))
+        });
+        assert!(result.is_ok());
+        assert!(source.shares.is_empty());
+        let verified = stats.verification();
+        assert!(verified.invalid >= 2);
+        assert_eq!(verified.checked, verified.invalid);
+    }
+
+    // The loop as main runs it: a real Stratum connection and worker threads
+    // against a pool that changes jobs, rejects shares, sends garbage and
+    // restarts.
+    #[test]
+    fn test_run_against_mock_pool() {
+        let wait = Duration::from_secs(30);
+        // Difficulty 1, so every hash is a share.
+        let pool = MockPool::start(mock::job("1", 1));
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let (events, event_rx) = event::channel();
+        let mut source = Stratum::login(
+            vec![Pool::new(&pool.url(), "wallet", "x", TlsConfig::default()).unwrap()],
+            policy,
+            events.clone(),
+        )
+        .unwrap();
+        let job = first_job(&event_rx).unwrap();
+        let stats = Stats::new(1);
+        let worker = Worker::init(
+            job,
+            NonZeroUsize::new(1).unwrap(),
+            true,
+            false,
+            CpuOptions::default(),
+            stats.clone(),
+            events.clone(),
+        )
+        .unwrap();
+
+        let controller = Controller::new(events);
+        let result = thread::scope(|scope| {
+            scope.spawn(|| {
+                assert!(wait_until(wait, || stats.shares().accepted > 0));
+
+                pool.reject_shares(Some("Low difficulty share"));
+                assert!(wait_until(wait, || stats.shares().rejected > 0));
+                pool.reject_shares(None);
+
+                pool.send_raw("{\"id\": 1, \"result\": ");
+                pool.push_job(mock::job("2", 1));
+                assert!(wait_until(wait, || stats
+                    .job()
+                    .is_some_and(|job| job.id == "2")));
+                let accepted = stats.shares().accepted;
+                assert!(wait_until(wait, || stats.shares().accepted > accepted));
+
+                // Let every share in flight be answered, so none is lost with
+                // the connection.
+                controller.send(Command::Pause).unwrap();
+                thread::sleep(Duration::from_millis(500));
+                assert!(wait_until(wait, || stats.shares().total()
+                    == pool.requests("submit").len() as u64));
+                pool.drop_connections();
+                assert!(wait_until(wait, || pool.logins() == 2
+                    && stats.connection().is_some_and(
+                        |c| c.failures == 1 && c.state == ConnectionState::Connected
+                    )));
+                controller.send(Command::Resume).unwrap();
+                let accepted = stats.shares().accepted;
+                assert!(wait_until(wait, || stats.shares().accepted > accepted));
+
+                controller.post(Command::Shutdown).unwrap();
+            });
+            run(&mut source, &worker, &stats, &event_rx, None)
+        });
+        assert!(result.is_ok(), "{:?}", result);
+        let shares = stats.shares();
+        assert_eq!(shares.total(), pool.requests("submit").len() as u64);
+        assert!(shares.rejected > 0);
+        assert_eq!(shares.timeout, 0);
+        assert!(pool
+            .requests("submit")
+            .iter()
+            .all(|submit| submit["job_id"] == "1" || submit["job_id"] == "2"));
+    }
+}
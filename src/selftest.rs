@@ -0,0 +1,166 @@
+use crate::{
+    dataset::Dataset,
+    error::{Error, Result},
+};
+
+use randomx_rs::{RandomXFlag, RandomXVM};
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// A known answer from the RandomX reference tests.
+#[derive(Debug, Clone, Copy)]
+pub struct TestVector {
+    pub key: &'static [u8],
+    pub input: &'static [u8],
+    /// Expected hash, hex encoded.
+    pub hash: &'static str,
+}
+
+pub const TEST_VECTORS: [TestVector; 5] = [
+    TestVector {
+        key: b"test key 000",
+        input: b"This is a test",
+        hash: "639183aae1bf4c9a35884cb46b09cad9175f04efd7684e7262a0ac1c2f0b4e3f",
+    },
+    TestVector {
+        key: b"test key 000",
+        input: b"Lorem ipsum dolor sit amet",
+        hash: "300a0adb47603dedb42228ccb2b211104f4da45af709cd7547cd049e9489c969",
+    },
+    TestVector {
+        key: b"test key 000",
+        input: b"sed do eiusmod tempor incididunt ut labore et dolore magna aliqua",
+        hash: "c36d4ed4191e617309867ed66a443be4075014e2b061bcdaf9ce7b721d2b77a8",
+    },
+    TestVector {
+        key: b"test key 001",
+        input: b"sed do eiusmod tempor incididunt ut labore et dolore magna aliqua",
+        hash: "e9ff4503201c0c2cca26d285c93ae883f9b1d30c9eb240b820756f2d5a7905fc",
+    },
+    // A Monero block hashing blob.
+    TestVector {
+        key: b"test key 001",
+        input: b"\x0b\x0b\x98\xbe\xa7\xe8\x05\xe0\x01\x0a\x21\x26\xd2\x87\xa2\xa0\xcc\x83\x3d\
+                 \x31\x2c\xb7\x86\x38\x5a\x7c\x2f\x9d\xe6\x9d\x25\x53\x7f\x58\x4a\x9b\xc9\
+                 \x97\x7b\x00\x00\x00\x00\x66\x6f\xd8\x75\x3b\xf6\x1a\x86\x31\xf1\x29\x84\
+                 \xe3\xfd\x44\xf4\x01\x4e\xca\x62\x92\x76\x81\x7b\x56\xf3\x2e\x9b\x68\xbd\
+                 \x82\xf4\x16",
+        hash: "c56414121acda1713c2f2a819d8ae38aed7c80c35c2a769298d34f03833cd5f1",
+    },
+];
+
+/// The test vectors keyed with `key`.
+pub fn vectors(key: &[u8]) -> impl Iterator<Item = &'static TestVector> + '_ {
+    TEST_VECTORS.iter().filter(move |v| v.key == key)
+}
+
+/// Hashes the test vectors keyed with `key` on `vm`, which has to be keyed
+/// with it too. Returns how many were checked.
+pub fn check(vm: &RandomXVM, key: &[u8]) -> Result<usize> {
+    let mut checked = 0;
+    for vector in vectors(key) {
+        let hash = hex::encode(vm.calculate_hash(vector.input)?);
+        if hash != vector.hash {
+            return Err(Error::Verification(format!(
+                "{:?} under key {:?} hashed to {}, expected {}",
+                String::from_utf8_lossy(vector.input),
+                String::from_utf8_lossy(vector.key),
+                hash,
+                vector.hash
+            )));
+        }
+        checked += 1;
+    }
+    Ok(checked)
+}
+
+/// Outcome of checking the test vectors in one mode.
+#[derive(Debug)]
+pub struct Report {
+    pub flags: RandomXFlag,
+    pub vectors: usize,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} mode: {} test vectors OK in {:.1}s ({:?})",
+            if self.flags.contains(RandomXFlag::FLAG_FULL_MEM) {
+                "Full"
+            } else {
+                "Light"
+            },
+            self.vectors,
+            self.elapsed.as_secs_f64(),
+            self.flags
+        )
+    }
+}
+
+/// Checks the test vectors for `keys` with `flags`, building a cache, and in
+/// full-memory mode a dataset, for each key.
+pub fn run(flags: RandomXFlag, keys: &[&[u8]]) -> Result<Report> {
+    let started = Instant::now();
+    let mut checked = 0;
+    for key in keys {
+        let dataset = Dataset::new(flags, key)?;
+        checked += check(&dataset.vm()?, key)?;
+    }
+    Ok(Report {
+        flags,
+        vectors: checked,
+        elapsed: started.elapsed(),
+    })
+}
+
+/// Every key among the test vectors, in order.
+pub fn keys() -> Vec<&'static [u8]> {
+    let mut keys: Vec<&[u8]> = TEST_VECTORS.iter().map(|v| v.key).collect();
+    keys.dedup();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{self, CpuOptions};
+
+    #[test]
+    fn test_light_mode() {
+        let flags = cpu::configure(&CpuOptions::default());
+        assert_eq!(run(flags, &keys()).unwrap().vectors, TEST_VECTORS.len());
+        let interpreted = cpu::configure(&CpuOptions {
+            no_jit: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            run(interpreted, &keys()).unwrap().vectors,
+            TEST_VECTORS.len()
+        );
+    }
+
+    // Building a full dataset takes a while, so this checks a single key.
+    #[test]
+    fn test_full_mode() {
+        let flags = cpu::configure(&CpuOptions::default()) | RandomXFlag::FLAG_FULL_MEM;
+        let report = run(flags, &[b"test key 000"]).unwrap();
+        assert_eq!(report.vectors, 3);
+        assert!(report
+            .to_string()
+            .starts_with("Full mode: 3 test vectors OK"));
+    }
+
+    #[test]
+    fn test_check_detects_wrong_key() {
+        let flags = cpu::configure(&CpuOptions::default());
+        let dataset = Dataset::new(flags, b"test key 001").unwrap();
+        assert!(matches!(
+            check(&dataset.vm().unwrap(), b"test key 000"),
+            Err(Error::Verification(_))
+        ));
+    }
+}
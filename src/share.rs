@@ -1,4 +1,4 @@
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Share {
     pub job_id: String,
     pub nonce: Vec<u8>,
@@ -14,3 +14,59 @@ impl Share {
         }
     }
 }
+
+/// A share at `nonce` with a zero hash, for tests.
+#[cfg(test)]
+pub(crate) fn test_share(job_id: &str, nonce: u32) -> Share {
+    Share::new(job_id.into(), nonce, vec![0; 32])
+}
+
+/// How the pool answered a submitted share.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShareOutcome {
+    Accepted,
+    Rejected {
+        code: i32,
+        message: String,
+    },
+    /// Rejected because the job had already been replaced.
+    Stale,
+    /// No answer arrived in time, e.g. because the connection dropped.
+    Timeout,
+}
+
+impl ShareOutcome {
+    /// Classifies a pool error. Pools report stale shares with an ordinary
+    /// error object, so they can only be told apart by the message.
+    pub fn from_error(code: i32, message: String) -> Self {
+        let lower = message.to_lowercase();
+        if ["stale", "expired", "job not found", "outdated"]
+            .iter()
+            .any(|s| lower.contains(s))
+        {
+            ShareOutcome::Stale
+        } else {
+            ShareOutcome::Rejected { code, message }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome_from_error() {
+        assert_eq!(
+            ShareOutcome::from_error(-1, "Block expired".into()),
+            ShareOutcome::Stale
+        );
+        assert_eq!(
+            ShareOutcome::from_error(-1, "Invalid job id".into()),
+            ShareOutcome::Rejected {
+                code: -1,
+                message: "Invalid job id".into()
+            }
+        );
+    }
+}
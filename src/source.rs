@@ -0,0 +1,250 @@
+use crate::{
+    error::{Error, Result},
+    event::Event,
+    job::Job,
+    share::{Share, ShareOutcome},
+    stratum::ConnectionState,
+};
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::Duration,
+};
+
+// How often `Polled` asks its source for news.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where jobs come from and found shares go to.
+///
+/// Implemented by `Stratum` for pools and `Daemon` for solo mining. Embedders
+/// can implement it to feed jobs from elsewhere, e.g. their own proxy, and
+/// run the miner on it with `miner::run`. The methods are called from the
+/// mining loop and must not block.
+///
+/// A source is given the sender of the mining loop's event channel when it
+/// is created, and sends new jobs as `Event::Job` (starting with the first
+/// one), share verdicts as `Event::Outcome` and connection changes as
+/// `Event::State`. Sources that would rather be asked implement `PullSource`
+/// as well and are wrapped in `Polled`. Sources that never learn what became
+/// of a share return false from `reports_outcomes`.
+pub trait WorkSource {
+    /// Hands in a share found for one of the source's jobs. The verdict
+    /// follows later as an `Event::Outcome`.
+    fn submit(&mut self, share: Share) -> Result<()>;
+
+    /// Called about once a minute to keep an idle connection open.
+    fn heartbeat(&mut self) -> Result<()>;
+
+    /// Whether every submitted share gets a verdict. Shutdown waits for
+    /// outstanding verdicts, so sources that never report any return false.
+    fn reports_outcomes(&self) -> bool {
+        true
+    }
+
+    /// Where work currently comes from, for logging.
+    fn endpoint(&self) -> String;
+
+    /// Closes the connection for good when mining stops. Nothing is called
+    /// on the source afterwards.
+    fn close(&mut self) {}
+
+    /// Moves to the endpoint at `index` among the source's configured ones.
+    /// Sources with a single endpoint refuse.
+    fn switch_pool(&mut self, index: usize) -> Result<()> {
+        Err(Error::Config(format!(
+            "Cannot switch to pool {}: {} has a single endpoint",
+            index,
+            self.endpoint()
+        )))
+    }
+}
+
+/// A source that hands out its news when asked instead of sending events.
+/// Wrap it in `Polled` to run it.
+pub trait PullSource {
+    /// Returns the next job if a new one arrived since the last call. An
+    /// error means no more jobs will come.
+    fn next_job(&mut self) -> Result<Option<Job>>;
+
+    /// Returns the verdict on a submitted share, if one arrived since the
+    /// last call.
+    fn next_outcome(&mut self) -> Option<(Share, ShareOutcome)>;
+
+    /// Returns the next connection state change, if any happened since the
+    /// last call. `Failed` ends the mining loop.
+    fn next_state(&mut self) -> Option<ConnectionState> {
+        None
+    }
+}
+
+/// Runs a `PullSource`. A pump thread polls it every 100ms and forwards jobs,
+/// outcomes and state changes to the mining loop's event channel. An error
+/// from `next_job` is forwarded as `ConnectionState::Failed`.
+pub struct Polled<S> {
+    source: Arc<Mutex<S>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl<S: WorkSource + PullSource + Send + 'static> Polled<S> {
+    /// Starts polling `source`, sending what it returns to `events`.
+    pub fn new(source: S, events: Sender<Event>) -> Self {
+        let source = Arc::new(Mutex::new(source));
+        let closed = Arc::new(AtomicBool::new(false));
+        let pumped = Arc::downgrade(&source);
+        let stop = closed.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                let Some(source) = pumped.upgrade() else {
+                    return;
+                };
+                if !pump(&mut *lock(&source), &events) {
+                    return;
+                }
+                drop(source);
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+        Self { source, closed }
+    }
+}
+
+// Forwards everything `source` has to report. Returns false once there is
+// nothing more to poll for.
+fn pump<S: WorkSource + PullSource>(source: &mut S, events: &Sender<Event>) -> bool {
+    loop {
+        let event = match source.next_job() {
+            Ok(Some(job)) => Event::Job(job),
+            Ok(None) => break,
+            Err(e) => {
+                tracing::error!("{} stopped sending jobs: {}", source.endpoint(), e);
+                let _ = events.send(Event::State(ConnectionState::Failed));
+                return false;
+            }
+        };
+        if events.send(event).is_err() {
+            return false;
+        }
+    }
+    while let Some((share, outcome)) = source.next_outcome() {
+        if events.send(Event::Outcome(share, outcome)).is_err() {
+            return false;
+        }
+    }
+    while let Some(state) = source.next_state() {
+        if events.send(Event::State(state)).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+impl<S: WorkSource> WorkSource for Polled<S> {
+    fn submit(&mut self, share: Share) -> Result<()> {
+        lock(&self.source).submit(share)
+    }
+
+    fn heartbeat(&mut self) -> Result<()> {
+        lock(&self.source).heartbeat()
+    }
+
+    fn reports_outcomes(&self) -> bool {
+        lock(&self.source).reports_outcomes()
+    }
+
+    fn endpoint(&self) -> String {
+        lock(&self.source).endpoint()
+    }
+
+    fn close(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        lock(&self.source).close();
+    }
+
+    fn switch_pool(&mut self, index: usize) -> Result<()> {
+        lock(&self.source).switch_pool(index)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{job::test_job, share::test_share};
+    use std::sync::mpsc::Receiver;
+
+    // Hands out queued jobs and accepts every share, the way embedders wrote
+    // sources before events.
+    #[derive(Default)]
+    struct Proxy {
+        jobs: Vec<Job>,
+        answered: Vec<Share>,
+        fail: bool,
+    }
+
+    impl PullSource for Proxy {
+        fn next_job(&mut self) -> Result<Option<Job>> {
+            if self.fail {
+                return Err(Error::Config("proxy gone".into()));
+            }
+            Ok(self.jobs.pop())
+        }
+
+        fn next_outcome(&mut self) -> Option<(Share, ShareOutcome)> {
+            Some((self.answered.pop()?, ShareOutcome::Accepted))
+        }
+    }
+
+    impl WorkSource for Proxy {
+        fn submit(&mut self, share: Share) -> Result<()> {
+            self.answered.push(share);
+            Ok(())
+        }
+
+        fn heartbeat(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn endpoint(&self) -> String {
+            "pull".into()
+        }
+    }
+
+    fn next_event(events: &Receiver<Event>) -> Event {
+        events.recv_timeout(Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn test_polled_forwards_jobs_and_outcomes() {
+        let job = test_job("pulled", 1);
+        let (events, event_rx) = crate::event::channel();
+        let mut source = Polled::new(
+            Proxy {
+                jobs: vec![job],
+                ..Default::default()
+            },
+            events,
+        );
+        assert!(matches!(next_event(&event_rx), Event::Job(job) if job.id == "pulled"));
+
+        source.submit(test_share("pulled", 1)).unwrap();
+        assert!(matches!(
+            next_event(&event_rx),
+            Event::Outcome(_, ShareOutcome::Accepted)
+        ));
+        assert_eq!(source.endpoint(), "pull");
+
+        lock(&source.source).fail = true;
+        assert!(matches!(
+            next_event(&event_rx),
+            Event::State(ConnectionState::Failed)
+        ));
+    }
+}
@@ -0,0 +1,397 @@
+use crate::{
+    job::Job, share::ShareOutcome, stratum::ConnectionState, target::Difficulty,
+    worker::ThreadState,
+};
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Windows hashrates are averaged over: 10 seconds, 60 seconds and 15 minutes.
+pub const HASHRATE_WINDOWS: [Duration; 3] = [
+    Duration::from_secs(10),
+    Duration::from_secs(60),
+    Duration::from_secs(15 * 60),
+];
+
+// Per-thread hash counts at one point in time.
+#[derive(Debug, Clone)]
+struct Sample {
+    at: Instant,
+    hashes: Vec<u64>,
+}
+
+/// The job currently being mined.
+#[derive(Debug, Clone)]
+pub struct JobInfo {
+    pub id: String,
+    pub difficulty: Difficulty,
+    pub seed: Vec<u8>,
+    pub received: Instant,
+}
+
+/// Shares by how the pool answered them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShareCounts {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub stale: u64,
+    pub timeout: u64,
+}
+
+impl ShareCounts {
+    pub fn total(&self) -> u64 {
+        self.accepted + self.rejected + self.stale + self.timeout
+    }
+}
+
+/// Shares the local verifier recomputed before they were submitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerifyCounts {
+    pub checked: u64,
+    /// Hashed differently or missed the target, so were never submitted.
+    pub invalid: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Connection {
+    pub endpoint: String,
+    pub state: ConnectionState,
+    /// When the current connection was made.
+    pub since: Instant,
+    /// Connections lost since startup.
+    pub failures: u64,
+}
+
+/// Central registry of miner telemetry. The worker threads, the dataset
+/// builder and the mining loop report to it; the log, the JSON API and the
+/// Prometheus metrics read from it.
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    // One counter per thread that ever ran; the first `threads` are running.
+    hashes: Mutex<Vec<Arc<AtomicU64>>>,
+    threads: AtomicUsize,
+    // Running threads that could not set up a VM and hash nothing.
+    failed_threads: Mutex<BTreeSet<usize>>,
+    history: Mutex<VecDeque<Sample>>,
+    highest: Mutex<Option<f64>>,
+    dataset_init: Mutex<Option<Duration>>,
+    shares: Mutex<ShareCounts>,
+    verified: Mutex<VerifyCounts>,
+    stale_dropped: AtomicU64,
+    job: Mutex<Option<JobInfo>>,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl Stats {
+    /// Creates the counters for `threads` worker threads and starts sampling
+    /// them for the hashrate windows.
+    pub fn new(threads: usize) -> Arc<Self> {
+        let stats = Arc::new(Self {
+            started: Instant::now(),
+            hashes: Mutex::new((0..threads).map(|_| Arc::default()).collect()),
+            threads: AtomicUsize::new(threads),
+            failed_threads: Mutex::new(BTreeSet::new()),
+            history: Mutex::new(VecDeque::new()),
+            highest: Mutex::new(None),
+            dataset_init: Mutex::new(None),
+            shares: Mutex::new(ShareCounts::default()),
+            verified: Mutex::new(VerifyCounts::default()),
+            stale_dropped: AtomicU64::new(0),
+            job: Mutex::new(None),
+            connection: Mutex::new(None),
+        });
+        stats.sample();
+        let weak = Arc::downgrade(&stats);
+        thread::spawn(move || sampler(weak));
+        stats
+    }
+
+    pub fn add_hashes(&self, thread: usize, hashes: u64) {
+        self.hash_counter(thread)
+            .fetch_add(hashes, Ordering::Relaxed);
+    }
+
+    /// The hash counter of `thread`, for worker threads to bump without
+    /// going through a lock on every hash.
+    pub fn hash_counter(&self, thread: usize) -> Arc<AtomicU64> {
+        let mut hashes = lock(&self.hashes);
+        if hashes.len() <= thread {
+            hashes.resize_with(thread + 1, Arc::default);
+        }
+        hashes[thread].clone()
+    }
+
+    /// Records that the worker now runs `threads` threads. Counters of
+    /// stopped threads still count towards the totals.
+    pub fn set_threads(&self, threads: usize) {
+        self.threads.store(threads, Ordering::Relaxed);
+        lock(&self.failed_threads).retain(|thread| *thread < threads);
+    }
+
+    /// Records whether `thread` is hashing or failed to set up a VM.
+    pub fn record_thread_state(&self, thread: usize, state: &ThreadState) {
+        let mut failed = lock(&self.failed_threads);
+        match state {
+            ThreadState::Hashing => failed.remove(&thread),
+            ThreadState::Failed(_) => failed.insert(thread),
+        };
+    }
+
+    /// Records how long the most recent RandomX cache and dataset took to
+    /// initialise.
+    pub fn record_dataset_init(&self, duration: Duration) {
+        *lock(&self.dataset_init) = Some(duration);
+    }
+
+    pub fn record_job(&self, job: &Job) {
+        *lock(&self.job) = Some(JobInfo {
+            id: job.id.clone(),
+            difficulty: job.difficulty(),
+            seed: job.seed.clone(),
+            received: Instant::now(),
+        });
+    }
+
+    pub fn record_outcome(&self, outcome: &ShareOutcome) {
+        let mut shares = lock(&self.shares);
+        match outcome {
+            ShareOutcome::Accepted => shares.accepted += 1,
+            ShareOutcome::Rejected { .. } => shares.rejected += 1,
+            ShareOutcome::Stale => shares.stale += 1,
+            ShareOutcome::Timeout => shares.timeout += 1,
+        }
+    }
+
+    pub fn record_verification(&self, valid: bool) {
+        let mut verified = lock(&self.verified);
+        verified.checked += 1;
+        verified.invalid += !valid as u64;
+    }
+
+    /// Records a share found for a replaced job and dropped instead of
+    /// submitted.
+    pub fn record_stale_dropped(&self) {
+        self.stale_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_state(&self, endpoint: String, state: ConnectionState) {
+        let mut connection = lock(&self.connection);
+        let failures = connection.as_ref().map_or(0, |c| c.failures);
+        let was_connected = connection
+            .as_ref()
+            .is_some_and(|c| c.state == ConnectionState::Connected);
+        *connection = Some(match state {
+            ConnectionState::Connected => Connection {
+                endpoint,
+                state,
+                since: Instant::now(),
+                failures,
+            },
+            _ => Connection {
+                endpoint,
+                state,
+                since: connection.as_ref().map_or(self.started, |c| c.since),
+                failures: failures + was_connected as u64,
+            },
+        });
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads.load(Ordering::Relaxed)
+    }
+
+    /// Indices of the running threads that hash nothing because they could
+    /// not set up a VM.
+    pub fn failed_threads(&self) -> Vec<usize> {
+        lock(&self.failed_threads).iter().copied().collect()
+    }
+
+    pub fn hashes_total(&self) -> u64 {
+        lock(&self.hashes)
+            .iter()
+            .map(|h| h.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Hashrate of each thread over each of `HASHRATE_WINDOWS`, `None` until
+    /// the miner has run for that long.
+    pub fn thread_hashrates(&self) -> Vec<[Option<f64>; 3]> {
+        (0..self.threads())
+            .map(|thread| self.thread_hashrate(thread))
+            .collect()
+    }
+
+    pub fn thread_hashrate(&self, thread: usize) -> [Option<f64>; 3] {
+        let history = lock(&self.history);
+        HASHRATE_WINDOWS.map(|window| rate(&history, window, Some(thread)))
+    }
+
+    /// Total hashrate over each of `HASHRATE_WINDOWS`.
+    pub fn hashrate(&self) -> [Option<f64>; 3] {
+        let history = lock(&self.history);
+        HASHRATE_WINDOWS.map(|window| rate(&history, window, None))
+    }
+
+    /// Highest total 10 second hashrate seen so far.
+    pub fn highest_hashrate(&self) -> Option<f64> {
+        *lock(&self.highest)
+    }
+
+    pub fn dataset_init(&self) -> Option<Duration> {
+        *lock(&self.dataset_init)
+    }
+
+    pub fn shares(&self) -> ShareCounts {
+        *lock(&self.shares)
+    }
+
+    pub fn verification(&self) -> VerifyCounts {
+        *lock(&self.verified)
+    }
+
+    pub fn stale_dropped(&self) -> u64 {
+        self.stale_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn job(&self) -> Option<JobInfo> {
+        lock(&self.job).clone()
+    }
+
+    pub fn connection(&self) -> Option<Connection> {
+        lock(&self.connection).clone()
+    }
+
+    fn sample(&self) {
+        let sample = Sample {
+            at: Instant::now(),
+            hashes: lock(&self.hashes)
+                .iter()
+                .map(|h| h.load(Ordering::Relaxed))
+                .collect(),
+        };
+        let mut history = lock(&self.history);
+        history.push_back(sample);
+        let keep = HASHRATE_WINDOWS[2] + SAMPLE_INTERVAL * 2;
+        while history
+            .front()
+            .is_some_and(|oldest| oldest.at.elapsed() > keep)
+        {
+            history.pop_front();
+        }
+        if let Some(current) = rate(&history, HASHRATE_WINDOWS[0], None) {
+            let mut highest = lock(&self.highest);
+            if highest.is_none_or(|highest| current > highest) {
+                *highest = Some(current);
+            }
+        }
+    }
+}
+
+fn sampler(stats: Weak<Stats>) {
+    loop {
+        thread::sleep(SAMPLE_INTERVAL);
+        match stats.upgrade() {
+            Some(stats) => stats.sample(),
+            None => return,
+        }
+    }
+}
+
+// Average rate between the newest sample and the newest one at least
+// `window` older, for one thread or all of them.
+fn rate(history: &VecDeque<Sample>, window: Duration, thread: Option<usize>) -> Option<f64> {
+    let newest = history.back()?;
+    let oldest = history
+        .iter()
+        .rev()
+        .find(|sample| newest.at.duration_since(sample.at) >= window)?;
+    let count = |sample: &Sample| match thread {
+        Some(thread) => sample.hashes.get(thread).copied().unwrap_or(0),
+        None => sample.hashes.iter().sum(),
+    };
+    let elapsed = newest.at.duration_since(oldest.at).as_secs_f64();
+    Some((count(newest) - count(oldest)) as f64 / elapsed)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_windows() {
+        let start = Instant::now();
+        let history: VecDeque<Sample> = (0..=60)
+            .map(|second| Sample {
+                at: start + Duration::from_secs(second),
+                // Thread 0 does 100 H/s, thread 1 speeds up to 300 H/s for
+                // the last 10 seconds.
+                hashes: vec![second * 100, second * 200 + second.saturating_sub(50) * 100],
+            })
+            .collect();
+
+        assert_eq!(rate(&history, HASHRATE_WINDOWS[0], Some(0)), Some(100.0));
+        assert_eq!(rate(&history, HASHRATE_WINDOWS[0], Some(1)), Some(300.0));
+        assert_eq!(rate(&history, HASHRATE_WINDOWS[0], None), Some(400.0));
+        assert_eq!(
+            rate(&history, HASHRATE_WINDOWS[1], None),
+            Some((6000.0 + 13000.0) / 60.0)
+        );
+        assert_eq!(rate(&history, HASHRATE_WINDOWS[2], None), None);
+    }
+
+    #[test]
+    fn test_connection_failures() {
+        let stats = Stats::new(1);
+        stats.record_state("a".into(), ConnectionState::Connected);
+        stats.record_state(
+            "a".into(),
+            ConnectionState::Reconnecting {
+                attempt: 1,
+                delay: Duration::from_secs(1),
+            },
+        );
+        stats.record_state(
+            "a".into(),
+            ConnectionState::Reconnecting {
+                attempt: 2,
+                delay: Duration::from_secs(2),
+            },
+        );
+        stats.record_state("b".into(), ConnectionState::Connected);
+        let connection = stats.connection().unwrap();
+        assert_eq!(connection.endpoint, "b");
+        assert_eq!(connection.failures, 1);
+    }
+
+    #[test]
+    fn test_failed_threads() {
+        let stats = Stats::new(4);
+        let failed = ThreadState::Failed("out of memory".into());
+        stats.record_thread_state(1, &failed);
+        stats.record_thread_state(3, &failed);
+        assert_eq!(stats.failed_threads(), [1, 3]);
+        stats.record_thread_state(1, &ThreadState::Hashing);
+        assert_eq!(stats.failed_threads(), [3]);
+        // Stopped threads are not failed ones.
+        stats.set_threads(2);
+        assert!(stats.failed_threads().is_empty());
+    }
+}
@@ -1,122 +1,670 @@
+#[cfg(test)]
+pub(crate) mod mock;
+mod pending;
+mod reconnect;
 mod rpc;
+mod transport;
 
+pub use reconnect::{ConnectionState, ReconnectPolicy};
+pub use transport::{PoolUrl, Scheme, TlsConfig};
 
+use crate::{
+    error::{Error, Result, StratumError},
+    event::Event,
+    job::Job,
+    share::{Share, ShareOutcome},
+    source::WorkSource,
+};
 
-use crate::{error::{Error, Result}, job::Job, share::Share};
-
+use pending::PendingSubmits;
 use rpc::{
+    message::Message,
     request::{KeepAlivedParams, LoginParams, Request, SubmitParams},
-    response::{LoginResult, Response, StatusResult},
+    response::{LoginResult, Response},
 };
-use serde::Deserialize;
 use std::{
-    io::{BufReader, BufWriter},
-    net::TcpStream,
-
-    sync::{mpsc::{self, Receiver}, Arc, Mutex},
+    io::{self, BufReader, BufWriter},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, Weak,
+    },
     thread,
+    time::Duration,
 };
+use transport::{Reader, Writer};
+
+// Shares without an answer after this long are reported as timed out.
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(30);
+// How often shares are checked against `SUBMIT_TIMEOUT`.
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A pool endpoint together with the credentials used to log in to it.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    pub url: PoolUrl,
+    pub user: String,
+    pub pass: String,
+    pub tls: TlsConfig,
+}
+
+impl Pool {
+    /// Fails on a malformed URL or certificate pin, so a bad configuration
+    /// is reported at startup.
+    pub fn new(url: &str, user: &str, pass: &str, mut tls: TlsConfig) -> Result<Self> {
+        tls.fingerprint = tls
+            .fingerprint
+            .as_deref()
+            .map(transport::normalize_fingerprint)
+            .transpose()?;
+        Ok(Self {
+            url: PoolUrl::parse(url)?,
+            user: user.into(),
+            pass: pass.into(),
+            tls,
+        })
+    }
+}
 
-#[derive(Deserialize, Debug)]
-#[serde(untagged)]
-enum PoolMessage {
-    Response(Response<StatusResult>),
-    NewJob(Request<Job>),
+// The write half of a logged-in connection. The listener clears it when the
+// connection drops and installs a new one after reconnecting.
+#[derive(Debug)]
+struct Session {
+    login_id: String,
+    writer: BufWriter<Writer>,
 }
+
+type Connection = (BufReader<Reader>, Session, Job);
+
 #[derive(Debug)]
 pub struct Stratum {
-    login_id: String,
-    writer: BufWriter<TcpStream>,
-    job_rx: Receiver<Job>,
+    pools: Arc<Vec<Pool>>,
+    active: Arc<AtomicUsize>,
+    session: Arc<Mutex<Option<Session>>>,
+    pending: Arc<PendingSubmits>,
+    switch_tx: Sender<(usize, Connection)>,
+    closed: Arc<AtomicBool>,
 }
 
 impl Stratum {
-    #[tracing::instrument]
-    pub fn login(url: &str, user: &str, pass: &str) -> Result<Self> {
-        let stream = TcpStream::connect(url)?;
-        stream.set_read_timeout(None)?;
-        let mut reader = BufReader::new(stream.try_clone()?);
-        let mut writer = BufWriter::new(stream.try_clone()?);
-
-        let (job_tx, job_rx) = mpsc::channel();
-
-        rpc::send(
-            &mut writer,
-            &Request::<LoginParams>::new(LoginParams {
-                login: user.into(),
-                pass: pass.into(),
-            }),
-        )?;
-        let response = rpc::recv::<Response<LoginResult>>(&mut reader)?;
-        if let Some(result) = response.result {
-            tracing::info!("success");
-            let LoginResult { id, job, .. } = result;
-            job_tx.send(job).map_err(Error::from)?;
+    /// Logs in to the first reachable pool in `pools`, which are ordered by
+    /// priority, and keeps a connection to one of them from then on. Jobs,
+    /// share verdicts and connection changes are sent to `events`.
+    #[tracing::instrument(skip_all)]
+    pub fn login(
+        pools: Vec<Pool>,
+        reconnect: ReconnectPolicy,
+        events: Sender<Event>,
+    ) -> Result<Self> {
+        let mut last_error = Error::Config("No pools configured".into());
+        let mut connected = None;
+        for (index, pool) in pools.iter().enumerate() {
+            match connect(pool) {
+                Ok(connection) => {
+                    connected = Some((index, connection));
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!("Login to {} failed: {}", pool.url, e);
+                    last_error = e;
+                }
+            }
+        }
+        let (index, (reader, session, job)) = connected.ok_or(last_error)?;
+        tracing::info!("Mining on {}", pools[index].url);
+
+        let (switch_tx, switch_rx) = mpsc::channel();
+        events.send(Event::Job(job)).map_err(Error::from)?;
+        events
+            .send(Event::State(ConnectionState::Connected))
+            .map_err(Error::from)?;
+
+        let pools = Arc::new(pools);
+        let active = Arc::new(AtomicUsize::new(index));
+        let session = Arc::new(Mutex::new(Some(session)));
+        let pending = Arc::new(PendingSubmits::new(events.clone()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let expiring = Arc::downgrade(&pending);
+        thread::spawn(move || loop {
+            thread::sleep(EXPIRE_INTERVAL);
+            let Some(pending) = expiring.upgrade() else {
+                return;
+            };
+            pending.expire(SUBMIT_TIMEOUT);
+        });
+        if pools.len() > 1 {
+            let prober = Prober {
+                pools: pools.clone(),
+                interval: reconnect.probe_interval,
+                active: active.clone(),
+                session: Arc::downgrade(&session),
+                switch_tx: switch_tx.clone(),
+            };
             thread::spawn(move || {
-                let span = tracing::info_span!("listener");
+                let span = tracing::info_span!("prober");
                 let _enter = span.enter();
-                loop {
-                    let msg = match rpc::recv::<PoolMessage>(&mut reader) {
-                        Ok(m) => m,
-                        Err(e) => {
-                            tracing::error!("Listener error: {}", e);
-                            break;
+                prober.run();
+            });
+        }
+        let supervisor = Supervisor {
+            pools: pools.clone(),
+            policy: reconnect,
+            active: active.clone(),
+            session: session.clone(),
+            pending: pending.clone(),
+            events,
+            switch_rx,
+            closed: closed.clone(),
+        };
+        thread::spawn(move || {
+            let span = tracing::info_span!("listener");
+            let _enter = span.enter();
+            supervisor.run(reader);
+        });
+        Ok(Self {
+            pools,
+            active,
+            session,
+            pending,
+            switch_tx,
+            closed,
+        })
+    }
+    /// Sends `share` to the pool. The pool's verdict, or a timeout if it
+    /// does not answer, is sent later as an `Event::Outcome`.
+    pub fn submit(&mut self, share: Share) -> Result<()> {
+        let pending = self.pending.clone();
+        self.with_session(|session| {
+            let request = Request::<SubmitParams>::new(SubmitParams {
+                id: session.login_id.clone(),
+                job_id: share.job_id.clone(),
+                nonce: share.nonce.clone(),
+                result: share.hash.clone(),
+            });
+            pending.insert(request.id, share);
+            rpc::send(&mut session.writer, &request).inspect_err(|_| {
+                pending.remove(request.id);
+            })
+        })
+    }
+    pub fn keep_alive(&mut self) -> Result<()> {
+        self.with_session(|session| {
+            rpc::send(
+                &mut session.writer,
+                &Request::<KeepAlivedParams>::new(KeepAlivedParams {
+                    id: session.login_id.clone(),
+                }),
+            )
+        })
+    }
+    /// Shuts the connection down for good; the listener stops instead of
+    /// reconnecting.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        if let Ok(mut session) = self.session.lock() {
+            if let Some(session) = session.take() {
+                let _ = session.writer.get_ref().shutdown();
+            }
+        }
+    }
+    pub fn is_connected(&self) -> bool {
+        self.session.lock().map(|s| s.is_some()).unwrap_or(false)
+    }
+    /// The pool currently mined on, or the last one if reconnecting.
+    pub fn active_pool(&self) -> &Pool {
+        &self.pools[self.active.load(Ordering::Relaxed)]
+    }
+    pub fn pools(&self) -> &[Pool] {
+        &self.pools
+    }
+    /// Logs in to `pools()[index]` in the background and moves mining over
+    /// to it once that succeeds. While on a backup pool the prober may later
+    /// switch back to a higher-priority one.
+    pub fn switch_pool(&self, index: usize) -> Result<()> {
+        if index >= self.pools.len() {
+            return Err(Error::Config(format!(
+                "No pool {}, {} configured",
+                index,
+                self.pools.len()
+            )));
+        }
+        let pools = self.pools.clone();
+        let session = Arc::downgrade(&self.session);
+        let switch_tx = self.switch_tx.clone();
+        thread::spawn(move || match connect(&pools[index]) {
+            Ok(connection) => {
+                tracing::info!("Switching to {}", pools[index].url);
+                hand_over(index, connection, &switch_tx, &session);
+            }
+            Err(e) => tracing::warn!("Login to {} failed: {}", pools[index].url, e),
+        });
+        Ok(())
+    }
+    fn with_session<F>(&self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Session) -> io::Result<()>,
+    {
+        let mut session = self
+            .session
+            .lock()
+            .map_err(|_| Error::Thread("Stratum session lock poisoned".into()))?;
+        let session = session.as_mut().ok_or(StratumError::NotConnected)?;
+        f(session).map_err(Error::from)
+    }
+}
+
+impl WorkSource for Stratum {
+    fn submit(&mut self, share: Share) -> Result<()> {
+        Stratum::submit(self, share)
+    }
+
+    fn heartbeat(&mut self) -> Result<()> {
+        self.keep_alive()
+    }
+
+    fn endpoint(&self) -> String {
+        self.active_pool().url.to_string()
+    }
+
+    fn switch_pool(&mut self, index: usize) -> Result<()> {
+        Stratum::switch_pool(self, index)
+    }
+
+    fn close(&mut self) {
+        Stratum::close(self)
+    }
+}
+
+fn connect(pool: &Pool) -> Result<Connection> {
+    let (reader, writer) = transport::connect(&pool.url, &pool.tls)?;
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+
+    rpc::send(
+        &mut writer,
+        &Request::<LoginParams>::new(LoginParams {
+            login: pool.user.clone(),
+            pass: pool.pass.clone(),
+        }),
+    )?;
+    let response = rpc::recv::<Response<LoginResult>, _>(&mut reader)?;
+    let id = response.id.unwrap_or_default();
+    match response.into_result() {
+        Ok(Some(LoginResult { id, job, .. })) => {
+            tracing::info!("success");
+            let session = Session {
+                login_id: id,
+                writer,
+            };
+            Ok((reader, session, job))
+        }
+        Ok(None) => Err(StratumError::EmptyResponse { id }.into()),
+        Err(e) => {
+            tracing::warn!("{}", e.message);
+            Err(StratumError::from(e).into())
+        }
+    }
+}
+
+// Owns the read half of the active connection. Whenever the listener exits it
+// either adopts a connection the prober made to a higher-priority pool or
+// fails over to the next pool in the list.
+struct Supervisor {
+    pools: Arc<Vec<Pool>>,
+    policy: ReconnectPolicy,
+    active: Arc<AtomicUsize>,
+    session: Arc<Mutex<Option<Session>>>,
+    pending: Arc<PendingSubmits>,
+    events: Sender<Event>,
+    switch_rx: Receiver<(usize, Connection)>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Supervisor {
+    // Returns once every pool keeps failing or the `Stratum` is closed or
+    // dropped.
+    fn run(self, mut reader: BufReader<Reader>) {
+        loop {
+            listen(&mut reader, &self.events, &self.pending, &self.closed);
+            if self.closed.load(Ordering::Relaxed) {
+                tracing::info!(
+                    "Disconnected from {}",
+                    self.pools[self.active.load(Ordering::Relaxed)].url
+                );
+                return;
+            }
+            if let Ok(mut session) = self.session.lock() {
+                *session = None;
+            }
+            let next = match self.switch_rx.try_recv() {
+                Ok(switch) => {
+                    // The old connection was shut down for the switch, so
+                    // the shares sent on it will never be answered.
+                    self.pending.resolve_all(ShareOutcome::Stale);
+                    Some(switch)
+                }
+                Err(_) => self.failover(),
+            };
+            match next.and_then(|(index, connection)| self.install(index, connection)) {
+                Some(new_reader) => reader = new_reader,
+                None => return,
+            }
+        }
+    }
+
+    fn failover(&self) -> Option<(usize, Connection)> {
+        let start = self.active.load(Ordering::Relaxed) + 1;
+        let mut attempt = 1;
+        loop {
+            if self.policy.exhausted(attempt) {
+                tracing::error!("Giving up after {} attempts", attempt - 1);
+                let _ = self.events.send(Event::State(ConnectionState::Failed));
+                return None;
+            }
+            let delay = self.policy.delay(attempt);
+            tracing::warn!(
+                "Connection lost, reconnecting in {:.1}s (attempt {})",
+                delay.as_secs_f64(),
+                attempt
+            );
+            self.events
+                .send(Event::State(ConnectionState::Reconnecting {
+                    attempt,
+                    delay,
+                }))
+                .ok()?;
+            thread::sleep(delay);
+            if let Ok(switch) = self.switch_rx.try_recv() {
+                return Some(switch);
+            }
+            for offset in 0..self.pools.len() {
+                let index = (start + offset) % self.pools.len();
+                let pool = &self.pools[index];
+                match connect(pool) {
+                    Ok(connection) => return Some((index, connection)),
+                    Err(e) => tracing::warn!("Login to {} failed: {}", pool.url, e),
+                }
+            }
+            attempt += 1;
+        }
+    }
+
+    fn install(&self, index: usize, connection: Connection) -> Option<BufReader<Reader>> {
+        let (reader, session, job) = connection;
+        self.active.store(index, Ordering::Relaxed);
+        if let Ok(mut current) = self.session.lock() {
+            *current = Some(session);
+        }
+        tracing::info!("Mining on {}", self.pools[index].url);
+        self.events.send(Event::Job(job)).ok()?;
+        self.events
+            .send(Event::State(ConnectionState::Connected))
+            .ok()?;
+        Some(reader)
+    }
+}
+
+// While mining on a backup pool, periodically logs in to the pools ranked
+// above it. The first one that succeeds is handed to the supervisor, and the
+// current connection is shut down so the listener lets go of it.
+struct Prober {
+    pools: Arc<Vec<Pool>>,
+    interval: Duration,
+    active: Arc<AtomicUsize>,
+    session: Weak<Mutex<Option<Session>>>,
+    switch_tx: Sender<(usize, Connection)>,
+}
+
+impl Prober {
+    fn run(self) {
+        loop {
+            thread::sleep(self.interval);
+            if self.session.strong_count() == 0 {
+                return;
+            }
+            let active = self.active.load(Ordering::Relaxed);
+            for (index, pool) in self.pools.iter().enumerate().take(active) {
+                let Ok(connection) = connect(pool) else {
+                    tracing::debug!("{} is still unavailable", pool.url);
+                    continue;
+                };
+                tracing::info!("Switching back to {}", pool.url);
+                if !hand_over(index, connection, &self.switch_tx, &self.session) {
+                    return;
+                }
+                break;
+            }
+        }
+    }
+}
+
+// Hands `connection` to the supervisor and shuts down the current one so the
+// listener lets go of it. Returns false once the supervisor is gone.
+fn hand_over(
+    index: usize,
+    connection: Connection,
+    switch_tx: &Sender<(usize, Connection)>,
+    session: &Weak<Mutex<Option<Session>>>,
+) -> bool {
+    if switch_tx.send((index, connection)).is_err() {
+        return false;
+    }
+    if let Some(session) = session.upgrade() {
+        if let Ok(session) = session.lock() {
+            if let Some(session) = session.as_ref() {
+                let _ = session.writer.get_ref().shutdown();
+            }
+        }
+    }
+    true
+}
+
+fn listen(
+    reader: &mut BufReader<Reader>,
+    events: &Sender<Event>,
+    pending: &PendingSubmits,
+    closed: &AtomicBool,
+) {
+    loop {
+        let line = match rpc::recv_line(reader) {
+            Ok(line) => line,
+            Err(e) => {
+                if !closed.load(Ordering::Relaxed) {
+                    tracing::error!("Listener error: {}", e);
+                }
+                return;
+            }
+        };
+        let msg = match Message::parse(&line) {
+            Ok(msg) => msg,
+            Err(e) => {
+                tracing::warn!("Skipping unrecognised message ({}): {}", e, line.trim());
+                continue;
+            }
+        };
+        match msg {
+            Message::Response(response) => {
+                let Some(id) = response.id else {
+                    // Pools answer a request they could not read with an
+                    // error and a null id. Submits are the only requests
+                    // sent while mining, so it belongs to the oldest one.
+                    match response.into_result() {
+                        Err(err) => {
+                            tracing::warn!("{}", err.message);
+                            let outcome = ShareOutcome::from_error(err.code, err.message);
+                            if !pending.resolve_oldest(outcome) {
+                                tracing::debug!("No submit pending for the error");
+                            }
                         }
-                    };
-                    match msg {
-                        PoolMessage::Response(response) => {
-                            if let Some(err) = response.error {
-                                tracing::warn!("{}", err.message);
+                        Ok(result) => tracing::debug!("Ignoring response without id: {:?}", result),
+                    }
+                    continue;
+                };
+                match response.into_result() {
+                    Err(err) => {
+                        tracing::warn!("{}", err.message);
+                        pending.resolve(id, ShareOutcome::from_error(err.code, err.message));
+                    }
+                    // Without an error, a missing result or status is an acknowledgement.
+                    Ok(result) => match result.as_ref().and_then(|r| r.get("status")) {
+                        None => {
+                            if pending.resolve(id, ShareOutcome::Accepted) {
+                                tracing::info!("accepted");
                             } else {
-                                match response.result.unwrap().status.as_str() {
-                                    "OK" => tracing::info!("accepted"),
-                                    "KEEPALIVED" => tracing::debug!("keepalived"),
-                                    _ => todo!(),
-                                }
+                                tracing::debug!("acknowledged");
                             }
                         }
-                        PoolMessage::NewJob(request) => {
-                            tracing::info!("new job");
-                            if let Err(e) = job_tx.send(request.params) {
-                                tracing::warn!("Failed to send job: {}", e);
+                        Some(status) if status == "OK" => {
+                            if pending.resolve(id, ShareOutcome::Accepted) {
+                                tracing::info!("accepted");
                             }
                         }
-                    }
+                        Some(status) if status == "KEEPALIVED" => tracing::debug!("keepalived"),
+                        Some(status) => {
+                            tracing::warn!("Unexpected status for request {}: {}", id, status);
+                            pending.resolve(
+                                id,
+                                ShareOutcome::Rejected {
+                                    code: 0,
+                                    message: format!("Unexpected status: {}", status),
+                                },
+                            );
+                        }
+                    },
                 }
-            });
-            Ok(Self {
-                login_id: id,
-                writer,
-                job_rx,
-            })
-        } else {
-            let msg = response.error.map(|e| e.message).unwrap_or_default();
-            tracing::warn!("{}", msg);
-            Err(Error::Stratum(msg))
+            }
+            Message::Job(job) => {
+                tracing::info!("new job");
+                if let Err(e) = events.send(Event::Job(job)) {
+                    tracing::warn!("Failed to send job: {}", e);
+                    return;
+                }
+            }
+            Message::Unknown { method, params } => {
+                tracing::debug!("Ignoring pool method {}: {}", method, params);
+            }
         }
     }
-    pub fn submit(&mut self, share: Share) -> Result<()> {
-        rpc::send(
-            &mut self.writer,
-            &Request::<SubmitParams>::new(SubmitParams {
-                id: self.login_id.clone(),
-                job_id: share.job_id,
-                nonce: share.nonce,
-                result: share.hash,
-            }),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::share::test_share;
+    use mock::{wait_until, MockPool};
+    use serde_json::json;
 
-        ).map_err(Error::from)
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn login(pool: &MockPool) -> (Stratum, Receiver<Event>) {
+        let pool = Pool::new(&pool.url(), "wallet", "x", TlsConfig::default()).unwrap();
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let (events, event_rx) = crate::event::channel();
+        (
+            Stratum::login(vec![pool], policy, events).unwrap(),
+            event_rx,
+        )
+    }
+
+    fn share(job_id: &str) -> Share {
+        test_share(job_id, 7)
+    }
+
+    fn next_event(events: &Receiver<Event>) -> Event {
+        events.recv_timeout(WAIT).expect("no event from the pool")
+    }
+
+    fn next_job(events: &Receiver<Event>) -> Job {
+        loop {
+            if let Event::Job(job) = next_event(events) {
+                return job;
+            }
+        }
+    }
+
+    fn next_outcome(events: &Receiver<Event>) -> ShareOutcome {
+        crate::event::next_outcome(events, WAIT).1
     }
-    pub fn keep_alive(&mut self) -> Result<()> {
-        rpc::send(
-            &mut self.writer,
-            &Request::<KeepAlivedParams>::new(KeepAlivedParams {
-                id: self.login_id.clone(),
-            }),
 
-        ).map_err(Error::from)
+    #[test]
+    fn test_login_submit_and_keepalive() {
+        let pool = MockPool::start(mock::job("1", 1000));
+        let (mut stratum, events) = login(&pool);
+        assert!(matches!(next_event(&events), Event::Job(job) if job.id == "1"));
+        assert!(matches!(
+            next_event(&events),
+            Event::State(ConnectionState::Connected)
+        ));
+        assert_eq!(pool.requests("login")[0]["login"], "wallet");
+
+        stratum.submit(share("1")).unwrap();
+        assert_eq!(next_outcome(&events), ShareOutcome::Accepted);
+        let submit = &pool.requests("submit")[0];
+        assert_eq!(submit["id"], "session-1");
+        assert_eq!(submit["nonce"], "07000000");
+
+        pool.reject_shares(Some("Low difficulty share"));
+        stratum.submit(share("1")).unwrap();
+        assert!(matches!(
+            next_outcome(&events),
+            ShareOutcome::Rejected { code: -1, .. }
+        ));
+        pool.reject_shares(None);
+
+        stratum.keep_alive().unwrap();
+        assert!(wait_until(WAIT, || pool.requests("keepalived").len() == 1));
     }
-    pub fn try_recv_job(&self) -> Result<Job> {
-        self.job_rx.try_recv().map_err(Error::from)
+
+    #[test]
+    fn test_new_jobs_and_malformed_lines() {
+        let pool = MockPool::start(mock::job("1", 1000));
+        let (mut stratum, events) = login(&pool);
+        next_job(&events);
+
+        pool.send_raw("{\"jsonrpc\": \"2.0\", \"method\": ");
+        pool.send_raw("not json at all");
+        pool.send_raw(&json!({ "jsonrpc": "2.0", "method": "job", "params": {} }).to_string());
+        pool.push_job(mock::job("2", 1000));
+        assert_eq!(next_job(&events).id, "2");
+        assert!(stratum.is_connected());
+
+        // The pool has moved on, so a share for the first job is stale.
+        stratum.submit(share("1")).unwrap();
+        assert_eq!(next_outcome(&events), ShareOutcome::Stale);
+    }
+
+    #[test]
+    fn test_reconnects_after_drop() {
+        let pool = MockPool::start(mock::job("1", 1000));
+        let (mut stratum, events) = login(&pool);
+        next_job(&events);
+        next_event(&events);
+
+        pool.push_job(mock::job("2", 1000));
+        pool.drop_connections();
+        let mut states = Vec::new();
+        let mut jobs = Vec::new();
+        while states.last() != Some(&ConnectionState::Connected) {
+            match next_event(&events) {
+                Event::State(state) => states.push(state),
+                Event::Job(job) => jobs.push(job.id),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert!(matches!(
+            states[0],
+            ConnectionState::Reconnecting { attempt: 1, .. }
+        ));
+        assert_eq!(pool.logins(), 2);
+        // The new session starts from the pool's current job.
+        assert_eq!(jobs.last().map(String::as_str), Some("2"));
+
+        stratum.submit(share("2")).unwrap();
+        assert_eq!(next_outcome(&events), ShareOutcome::Accepted);
+        assert_eq!(pool.requests("submit")[0]["id"], "session-2");
     }
 }
@@ -0,0 +1,197 @@
+use crate::target::{Difficulty, Target};
+
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const WAIT_POLL: Duration = Duration::from_millis(10);
+
+/// A job notification's params for difficulty `difficulty` on the all-zero
+/// seed.
+pub fn job(id: &str, difficulty: u64) -> Value {
+    json!({
+        "job_id": id,
+        "blob": format!("0707{}", "00".repeat(74)),
+        "seed_hash": "00".repeat(32),
+        "target": hex::encode(Target::from_difficulty(Difficulty(difficulty)).compact()),
+        "height": 1,
+    })
+}
+
+/// Polls `done` until it holds, for at most `timeout`. Returns whether it did.
+pub fn wait_until(timeout: Duration, mut done: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+    while !done() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(WAIT_POLL);
+    }
+    true
+}
+
+#[derive(Default)]
+struct Shared {
+    // Handed out on login and the only job shares are accepted for.
+    job: Mutex<Value>,
+    // Write halves of the open connections. Every write goes through this
+    // lock so responses and notifications never interleave.
+    clients: Mutex<Vec<TcpStream>>,
+    requests: Mutex<Vec<Value>>,
+    reject: Mutex<Option<String>>,
+    logins: AtomicUsize,
+    closed: AtomicBool,
+}
+
+/// An in-process pool for tests. It speaks the login, job, submit and
+/// keepalived methods on localhost and can be told to push jobs, reject
+/// shares, drop connections and send malformed lines. Shares for any job but
+/// the current one are rejected as stale.
+pub struct MockPool {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl MockPool {
+    /// Listens on a free port and hands `job` to every login.
+    pub fn start(job: Value) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shared = Arc::new(Shared {
+            job: Mutex::new(job),
+            ..Default::default()
+        });
+        let accepting = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accepting.closed.load(Ordering::Relaxed) {
+                    return;
+                }
+                let Ok(stream) = stream else { continue };
+                let shared = accepting.clone();
+                thread::spawn(move || serve(&shared, stream));
+            }
+        });
+        Self { addr, shared }
+    }
+
+    pub fn url(&self) -> String {
+        format!("stratum+tcp://{}", self.addr)
+    }
+
+    /// Makes `job` current and notifies every connection of it.
+    pub fn push_job(&self, job: Value) {
+        *lock(&self.shared.job) = job.clone();
+        self.broadcast(&json!({ "jsonrpc": "2.0", "method": "job", "params": job }).to_string());
+    }
+
+    /// Rejects every share with `message` from now on, or accepts valid
+    /// shares again with `None`.
+    pub fn reject_shares(&self, message: Option<&str>) {
+        *lock(&self.shared.reject) = message.map(Into::into);
+    }
+
+    /// Sends `line` as is to every connection.
+    pub fn send_raw(&self, line: &str) {
+        self.broadcast(line);
+    }
+
+    /// Closes every open connection, as a pool restart would.
+    pub fn drop_connections(&self) {
+        for client in lock(&self.shared.clients).drain(..) {
+            let _ = client.shutdown(Shutdown::Both);
+        }
+    }
+
+    pub fn logins(&self) -> usize {
+        self.shared.logins.load(Ordering::Relaxed)
+    }
+
+    /// Params of every request with `method` received so far.
+    pub fn requests(&self, method: &str) -> Vec<Value> {
+        lock(&self.shared.requests)
+            .iter()
+            .filter(|r| r["method"] == method)
+            .map(|r| r["params"].clone())
+            .collect()
+    }
+
+    fn broadcast(&self, line: &str) {
+        let mut clients = lock(&self.shared.clients);
+        for client in clients.iter_mut() {
+            let _ = writeln!(client, "{}", line);
+        }
+    }
+}
+
+impl Drop for MockPool {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        self.drop_connections();
+        // Wakes the accept loop so it sees `closed`.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(shared: &Shared, stream: TcpStream) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { return };
+        let Ok(request) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        lock(&shared.requests).push(request.clone());
+        let id = request["id"].clone();
+        let result = match request["method"].as_str() {
+            Some("login") => {
+                let n = shared.logins.fetch_add(1, Ordering::Relaxed) + 1;
+                if let Ok(client) = writer.try_clone() {
+                    lock(&shared.clients).push(client);
+                }
+                Ok(json!({
+                    "id": format!("session-{}", n),
+                    "job": *lock(&shared.job),
+                    "status": "OK",
+                }))
+            }
+            Some("submit") => {
+                let current = lock(&shared.job)["job_id"].clone();
+                match lock(&shared.reject).clone() {
+                    Some(message) => Err(message),
+                    None if request["params"]["job_id"] != current => Err("Block expired".into()),
+                    None => Ok(json!({ "status": "OK" })),
+                }
+            }
+            Some("keepalived") => Ok(json!({ "status": "KEEPALIVED" })),
+            _ => Err("Unknown method".into()),
+        };
+        let response = match result {
+            Ok(result) => json!({ "id": id, "jsonrpc": "2.0", "error": null, "result": result }),
+            Err(message) => json!({
+                "id": id,
+                "jsonrpc": "2.0",
+                "error": { "code": -1, "message": message },
+                "result": null,
+            }),
+        };
+        // Hold the lock while writing so a notification cannot split the line.
+        let _clients = lock(&shared.clients);
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
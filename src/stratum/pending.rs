@@ -0,0 +1,160 @@
+use crate::{
+    event::Event,
+    share::{Share, ShareOutcome},
+};
+
+use std::{
+    collections::HashMap,
+    sync::{mpsc::Sender, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Submitted shares waiting for the pool's answer, keyed by request id.
+#[derive(Debug)]
+pub struct PendingSubmits {
+    requests: Mutex<HashMap<u32, (Share, Instant)>>,
+    events: Sender<Event>,
+}
+
+impl PendingSubmits {
+    /// Outcomes are sent to `events`.
+    pub fn new(events: Sender<Event>) -> Self {
+        Self {
+            requests: Mutex::new(HashMap::new()),
+            events,
+        }
+    }
+
+    pub fn insert(&self, id: u32, share: Share) {
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.insert(id, (share, Instant::now()));
+        }
+    }
+
+    pub fn remove(&self, id: u32) -> Option<Share> {
+        let mut requests = self.requests.lock().ok()?;
+        requests.remove(&id).map(|(share, _)| share)
+    }
+
+    /// Reports the outcome of request `id`. Returns false if it was not a
+    /// pending submit, e.g. a keepalive or a share that already timed out.
+    pub fn resolve(&self, id: u32, outcome: ShareOutcome) -> bool {
+        match self.remove(id) {
+            Some(share) => {
+                let _ = self.events.send(Event::Outcome(share, outcome));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reports the outcome of the longest waiting submit, for an answer that
+    /// does not say which request it belongs to. Returns false if none was
+    /// pending.
+    pub fn resolve_oldest(&self, outcome: ShareOutcome) -> bool {
+        let oldest = self.requests.lock().ok().and_then(|requests| {
+            requests
+                .iter()
+                .min_by_key(|(id, (_, sent))| (*sent, **id))
+                .map(|(id, _)| *id)
+        });
+        oldest.is_some_and(|id| self.resolve(id, outcome))
+    }
+
+    /// Reports every pending share with `outcome`, e.g. when the connection
+    /// they were sent on is shut down and cannot answer any more.
+    pub fn resolve_all(&self, outcome: ShareOutcome) {
+        let Ok(mut requests) = self.requests.lock() else {
+            return;
+        };
+        for (_, (share, _)) in requests.drain() {
+            let _ = self.events.send(Event::Outcome(share, outcome.clone()));
+        }
+    }
+
+    /// Reports every share that has waited longer than `timeout` as timed out.
+    pub fn expire(&self, timeout: Duration) {
+        let Ok(mut requests) = self.requests.lock() else {
+            return;
+        };
+        let expired: Vec<u32> = requests
+            .iter()
+            .filter(|(_, (_, sent))| sent.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some((share, _)) = requests.remove(&id) {
+                let _ = self
+                    .events
+                    .send(Event::Outcome(share, ShareOutcome::Timeout));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::share::test_share;
+    use std::sync::mpsc::Receiver;
+
+    fn next_outcome(events: &Receiver<Event>) -> (Share, ShareOutcome) {
+        crate::event::next_outcome(events, Duration::ZERO)
+    }
+
+    #[test]
+    fn test_resolve_and_expire() {
+        let (tx, rx) = crate::event::channel();
+        let pending = PendingSubmits::new(tx);
+        pending.insert(7, test_share("a", 1));
+        pending.insert(8, test_share("b", 2));
+
+        assert!(pending.resolve(7, ShareOutcome::Accepted));
+        assert!(!pending.resolve(7, ShareOutcome::Accepted));
+        let (share, outcome) = next_outcome(&rx);
+        assert_eq!(share.job_id, "a");
+        assert_eq!(outcome, ShareOutcome::Accepted);
+
+        pending.expire(Duration::ZERO);
+        let (share, outcome) = next_outcome(&rx);
+        assert_eq!(share.job_id, "b");
+        assert_eq!(outcome, ShareOutcome::Timeout);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_resolve_oldest() {
+        let (tx, rx) = crate::event::channel();
+        let pending = PendingSubmits::new(tx);
+        assert!(!pending.resolve_oldest(ShareOutcome::Stale));
+        pending.insert(9, test_share("a", 1));
+        pending.insert(3, test_share("b", 2));
+        pending.insert(5, test_share("c", 3));
+
+        assert!(pending.resolve_oldest(ShareOutcome::Stale));
+        let (share, outcome) = next_outcome(&rx);
+        assert_eq!(share.job_id, "a");
+        assert_eq!(outcome, ShareOutcome::Stale);
+
+        assert!(pending.resolve_oldest(ShareOutcome::Stale));
+        assert_eq!(next_outcome(&rx).0.job_id, "b");
+    }
+
+    #[test]
+    fn test_resolve_all() {
+        let (tx, rx) = crate::event::channel();
+        let pending = PendingSubmits::new(tx);
+        pending.insert(3, test_share("a", 1));
+        pending.insert(5, test_share("b", 2));
+
+        pending.resolve_all(ShareOutcome::Stale);
+        let mut stale = [next_outcome(&rx), next_outcome(&rx)];
+        stale.sort_by(|a, b| a.0.job_id.cmp(&b.0.job_id));
+        assert_eq!(stale[0].0.job_id, "a");
+        assert_eq!(stale[1].0.job_id, "b");
+        assert!(stale
+            .iter()
+            .all(|(_, outcome)| *outcome == ShareOutcome::Stale));
+        assert!(!pending.resolve(3, ShareOutcome::Accepted));
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Logged in and listening for jobs.
+    Connected,
+    /// The connection was lost; the next round of login attempts starts after `delay`.
+    Reconnecting { attempt: u32, delay: Duration },
+    /// Every pool failed for `ReconnectPolicy::max_attempts` consecutive rounds.
+    Failed,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Number of consecutive rounds in which every pool failed before giving
+    /// up, `None` retries forever.
+    pub max_attempts: Option<u32>,
+    /// How often to try higher-priority pools while mining on a backup.
+    pub probe_interval: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: None,
+            probe_interval: Duration::from_secs(120),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Delay before login round `attempt` (starting at 1): the exponential
+    /// backoff ceiling with full jitter applied to its upper half, so a fleet
+    /// that lost the same pool does not reconnect in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = attempt.saturating_sub(1).min(31);
+        let ceiling = self
+            .initial_delay
+            .saturating_mul(1 << exp)
+            .min(self.max_delay);
+        let half = ceiling / 2;
+        let jitter = half.mul_f64(random_fraction());
+        half + jitter
+    }
+
+    pub fn exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts.is_some_and(|max| attempt > max)
+    }
+}
+
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_grows_and_caps() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(8),
+            max_attempts: Some(3),
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let first = policy.delay(1);
+            assert!(first >= Duration::from_millis(500) && first <= Duration::from_secs(1));
+            let third = policy.delay(3);
+            assert!(third >= Duration::from_secs(2) && third <= Duration::from_secs(4));
+            assert!(policy.delay(40) <= Duration::from_secs(8));
+        }
+        assert!(!policy.exhausted(3));
+        assert!(policy.exhausted(4));
+    }
+}
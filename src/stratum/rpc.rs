@@ -1,24 +1,37 @@
+pub mod message;
 pub mod request;
 pub mod response;
 
 use request::Request;
 use serde::{de::DeserializeOwned, Serialize};
-use std::{
-    io::{self, BufReader, BufWriter, Write},
-    net::TcpStream,
-};
+use std::io::{self, BufRead, Write};
 
-pub fn send<S: Serialize>(
-    writer: &mut BufWriter<TcpStream>,
-    request: &Request<S>,
-) -> io::Result<()> {
+pub fn send<W: Write, S: Serialize>(writer: &mut W, request: &Request<S>) -> io::Result<()> {
     serde_json::to_writer(&mut *writer, request)?;
     writeln!(writer)?;
     writer.flush()?;
     Ok(())
 }
 
-pub fn recv<D: DeserializeOwned>(reader: &mut BufReader<TcpStream>) -> serde_json::Result<D> {
-    let mut de = serde_json::Deserializer::from_reader(reader);
-    D::deserialize(&mut de)
+/// Reads the next non-empty line. Stratum messages are newline delimited, so
+/// a malformed message only costs its own line.
+pub fn recv_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed by pool",
+            ));
+        }
+        if !line.trim().is_empty() {
+            return Ok(line);
+        }
+    }
+}
+
+pub fn recv<D: DeserializeOwned, R: BufRead>(reader: &mut R) -> crate::Result<D> {
+    let line = recv_line(reader)?;
+    Ok(serde_json::from_str(&line)?)
 }
@@ -0,0 +1,95 @@
+use super::response::Response;
+use crate::{
+    error::{Result, StratumError},
+    job::Job,
+};
+
+use serde_json::Value;
+
+/// Anything a pool may send once logged in.
+#[derive(Debug)]
+pub enum Message {
+    /// A `job` notification carrying new work.
+    Job(Job),
+    /// A response to one of our requests. The result is left untyped because
+    /// its shape depends on which request it answers.
+    Response(Response<Value>),
+    /// A pool-initiated method we do not act on, e.g. `getjob` or `set_extranonce`.
+    Unknown { method: String, params: Value },
+}
+
+impl Message {
+    pub fn parse(line: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(line)?;
+        match value.get("method").and_then(Value::as_str) {
+            Some("job") => {
+                let params = value.get("params").cloned().unwrap_or_default();
+                Ok(Message::Job(serde_json::from_value(params)?))
+            }
+            Some(method) => Ok(Message::Unknown {
+                method: method.into(),
+                params: value.get("params").cloned().unwrap_or_default(),
+            }),
+            None if value.get("id").is_some() => {
+                Ok(Message::Response(serde_json::from_value(value)?))
+            }
+            None => {
+                Err(StratumError::Protocol("Message has neither a method nor an id".into()).into())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JOB: &str = r#"{"jsonrpc":"2.0","method":"job","params":{"job_id":"1","blob":"0707","seed_hash":"00","target":"b88d0600"}}"#;
+
+    #[test]
+    fn test_parse_job() {
+        match Message::parse(JOB).unwrap() {
+            Message::Job(job) => assert_eq!(job.id, "1"),
+            other => panic!("Expected job, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_responses() {
+        let ok = r#"{"id":3,"jsonrpc":"2.0","error":null,"result":{"status":"OK"}}"#;
+        match Message::parse(ok).unwrap() {
+            Message::Response(response) => {
+                assert_eq!(response.id, Some(3));
+                assert_eq!(response.result.unwrap()["status"], "OK");
+                assert!(response.error.is_none());
+            }
+            other => panic!("Expected response, got {:?}", other),
+        }
+
+        let err =
+            r#"{"id":4,"jsonrpc":"2.0","error":{"code":-1,"message":"Low difficulty share"}}"#;
+        match Message::parse(err).unwrap() {
+            Message::Response(response) => {
+                assert!(response.result.is_none());
+                assert_eq!(response.error.unwrap().code, -1);
+            }
+            other => panic!("Expected response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_unknown_method() {
+        let msg = r#"{"jsonrpc":"2.0","method":"set_extranonce","params":["0a",4]}"#;
+        match Message::parse(msg).unwrap() {
+            Message::Unknown { method, .. } => assert_eq!(method, "set_extranonce"),
+            other => panic!("Expected unknown method, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert!(Message::parse("{not json").is_err());
+        assert!(Message::parse(r#"{"jsonrpc":"2.0"}"#).is_err());
+        assert!(Message::parse(r#"{"method":"job","params":{"job_id":"1"}}"#).is_err());
+    }
+}
@@ -1,4 +1,13 @@
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+// Ids are unique for the lifetime of the process, so a response can never be
+// matched to a request sent on an earlier connection.
+fn next_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Request<P> {
@@ -19,7 +28,7 @@ impl Request<LoginParams> {
         Request::<LoginParams> {
             method: "login".into(),
             params,
-            id: 1,
+            id: next_id(),
         }
     }
 }
@@ -39,7 +48,7 @@ impl Request<SubmitParams> {
         Request::<SubmitParams> {
             method: "submit".into(),
             params,
-            id: 1,
+            id: next_id(),
         }
     }
 }
@@ -54,7 +63,7 @@ impl Request<KeepAlivedParams> {
         Request::<KeepAlivedParams> {
             method: "keepalived".into(),
             params,
-            id: 1,
+            id: next_id(),
         }
     }
 }
@@ -1,19 +1,54 @@
-use crate::job::Job;
-use serde::Deserialize;
+use crate::{error::StratumError, job::Job};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
 
 #[derive(Deserialize, Debug)]
 pub struct Error {
-    #[allow(dead_code)]
+    /// Some pools send the code as a string or leave it out; those that
+    /// cannot be read as a number become 0.
+    #[serde(default, deserialize_with = "lenient_code")]
     pub code: i32,
     pub message: String,
 }
 
+fn lenient_code<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    let code = Value::deserialize(deserializer)?;
+    Ok(match &code {
+        Value::Number(n) => n.as_i64().and_then(|n| i32::try_from(n).ok()),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+    .unwrap_or(0))
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Response<R> {
     pub result: Option<R>,
     pub error: Option<Error>,
-    #[allow(dead_code)]
-    pub id: u32,
+    /// Null when the pool could not tell which request it answers, e.g.
+    /// because it failed to parse it.
+    pub id: Option<u32>,
+}
+
+impl<R> Response<R> {
+    /// Splits the response into its outcome. An error object wins over any
+    /// result, and a response with neither is a bare acknowledgement, which
+    /// some pools send instead of a status.
+    pub fn into_result(self) -> Result<Option<R>, Error> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.result),
+        }
+    }
+}
+
+impl From<Error> for StratumError {
+    fn from(error: Error) -> Self {
+        StratumError::Pool {
+            code: error.code,
+            message: error.message,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -24,8 +59,44 @@ pub struct LoginResult {
     pub status: String,
 }
 
-// Responses to subtit and keepalived requests differ only in the status value
-#[derive(Debug, Deserialize)]
-pub struct StatusResult {
-    pub status: String,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Result<Option<Value>, Error> {
+        serde_json::from_str::<Response<Value>>(json)
+            .unwrap()
+            .into_result()
+    }
+
+    #[test]
+    fn test_into_result_combinations() {
+        let ok = parse(r#"{"id":1,"result":{"status":"OK"},"error":null}"#).unwrap();
+        assert_eq!(ok.unwrap()["status"], "OK");
+
+        assert!(parse(r#"{"id":1,"result":null,"error":null}"#)
+            .unwrap()
+            .is_none());
+        assert!(parse(r#"{"id":1}"#).unwrap().is_none());
+
+        let err = parse(r#"{"id":1,"result":null,"error":{"code":-1,"message":"Bad"}}"#);
+        assert_eq!(err.unwrap_err().code, -1);
+
+        let both =
+            parse(r#"{"id":1,"result":{"status":"OK"},"error":{"code":-2,"message":"Bad"}}"#);
+        assert_eq!(both.unwrap_err().code, -2);
+    }
+
+    #[test]
+    fn test_null_id_and_odd_codes() {
+        let response: Response<Value> =
+            serde_json::from_str(r#"{"id":null,"error":{"code":"-5","message":"Bad"}}"#).unwrap();
+        assert_eq!(response.id, None);
+        assert_eq!(response.into_result().unwrap_err().code, -5);
+
+        let err = parse(r#"{"id":1,"error":{"code":"oops","message":"Bad"}}"#);
+        assert_eq!(err.unwrap_err().code, 0);
+        let err = parse(r#"{"id":1,"error":{"message":"Bad"}}"#);
+        assert_eq!(err.unwrap_err().code, 0);
+    }
 }
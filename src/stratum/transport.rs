@@ -0,0 +1,290 @@
+use crate::error::{Error, Result, StratumError};
+
+use native_tls::{HandshakeError, TlsConnector, TlsStream};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Tcp,
+    Ssl,
+}
+
+/// A pool address of the form `[stratum+tcp://|stratum+ssl://]host:port`.
+///
+/// Addresses without a scheme are treated as plain TCP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolUrl {
+    pub scheme: Scheme,
+    pub host: String,
+    pub port: u16,
+}
+
+impl PoolUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        let (scheme, rest) = if let Some(rest) = url.strip_prefix("stratum+ssl://") {
+            (Scheme::Ssl, rest)
+        } else if let Some(rest) = url.strip_prefix("stratum+tcp://") {
+            (Scheme::Tcp, rest)
+        } else if url.contains("://") {
+            return Err(Error::Config(format!(
+                "Unsupported pool URL scheme: {}",
+                url
+            )));
+        } else {
+            (Scheme::Tcp, url)
+        };
+        let rest = rest.trim_end_matches('/');
+        let (host, port) = rest
+            .rsplit_once(':')
+            .ok_or_else(|| Error::Config(format!("Pool URL is missing a port: {}", url)))?;
+        if host.is_empty() {
+            return Err(Error::Config(format!(
+                "Pool URL is missing a host: {}",
+                url
+            )));
+        }
+        let port = port
+            .parse()
+            .map_err(|_| Error::Config(format!("Invalid port in pool URL: {}", url)))?;
+        Ok(Self {
+            scheme,
+            host: host.into(),
+            port,
+        })
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.scheme == Scheme::Ssl
+    }
+}
+
+impl FromStr for PoolUrl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for PoolUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.scheme {
+            Scheme::Tcp => "stratum+tcp",
+            Scheme::Ssl => "stratum+ssl",
+        };
+        write!(f, "{}://{}:{}", scheme, self.host, self.port)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    /// Accept certificates that fail verification, e.g. self-signed test pools.
+    pub insecure: bool,
+    /// Hex encoded SHA-256 fingerprint the pool certificate must match,
+    /// optionally with `:` between the bytes.
+    pub fingerprint: Option<String>,
+}
+
+/// Read half of a pool connection.
+///
+/// TLS streams cannot be split, so the listener and the writer share one
+/// behind a mutex. The listener only takes it for reads that do not block,
+/// and waits for the pool on `socket`, a clone of the underlying socket, in
+/// between.
+#[derive(Debug)]
+pub enum Reader {
+    Plain(TcpStream),
+    Tls {
+        stream: Arc<Mutex<TlsStream<TcpStream>>>,
+        socket: TcpStream,
+    },
+}
+
+/// Write half of a pool connection.
+#[derive(Debug)]
+pub enum Writer {
+    Plain(TcpStream),
+    Tls(Arc<Mutex<TlsStream<TcpStream>>>),
+}
+
+pub fn connect(url: &PoolUrl, tls: &TlsConfig) -> Result<(Reader, Writer)> {
+    let stream = TcpStream::connect((url.host.as_str(), url.port))?;
+    if !url.is_tls() {
+        stream.set_read_timeout(None)?;
+        return Ok((Reader::Plain(stream.try_clone()?), Writer::Plain(stream)));
+    }
+
+    // A pinned fingerprint replaces chain verification, which is what makes
+    // pinning useful for pools with self-signed certificates.
+    let skip_verify = tls.insecure || tls.fingerprint.is_some();
+    let connector = TlsConnector::builder()
+        .danger_accept_invalid_certs(skip_verify)
+        .danger_accept_invalid_hostnames(skip_verify)
+        .build()?;
+    let stream = connector.connect(&url.host, stream).map_err(|e| match e {
+        HandshakeError::Failure(e) => Error::Tls(e),
+        HandshakeError::WouldBlock(_) => {
+            StratumError::Protocol("TLS handshake interrupted".into()).into()
+        }
+    })?;
+
+    if let Some(expected) = &tls.fingerprint {
+        let actual = fingerprint(&stream)?;
+        if normalize_fingerprint(expected)? != actual {
+            return Err(StratumError::FingerprintMismatch {
+                expected: expected.clone(),
+                actual,
+            }
+            .into());
+        }
+        tracing::debug!("TLS fingerprint verified");
+    } else if tls.insecure {
+        tracing::warn!("TLS certificate verification disabled");
+    }
+
+    let socket = stream.get_ref().try_clone()?;
+    let stream = Arc::new(Mutex::new(stream));
+    Ok((
+        Reader::Tls {
+            stream: stream.clone(),
+            socket,
+        },
+        Writer::Tls(stream),
+    ))
+}
+
+impl Writer {
+    /// Closes both directions of the connection, which also wakes a listener
+    /// blocked on the read half.
+    pub fn shutdown(&self) -> io::Result<()> {
+        match self {
+            Writer::Plain(stream) => stream.shutdown(Shutdown::Both),
+            Writer::Tls(stream) => stream
+                .lock()
+                .map_err(|_| poisoned())?
+                .get_ref()
+                .shutdown(Shutdown::Both),
+        }
+    }
+}
+
+fn fingerprint(stream: &TlsStream<TcpStream>) -> Result<String> {
+    let cert = stream
+        .peer_certificate()?
+        .ok_or_else(|| StratumError::Protocol("Pool did not present a certificate".into()))?;
+    Ok(hex::encode(Sha256::digest(cert.to_der()?)))
+}
+
+/// Lowercases a certificate pin and drops the `:` between its bytes. Pins
+/// that are not 64 hex digits, i.e. not a SHA-256 hash, are refused.
+pub(crate) fn normalize_fingerprint(fingerprint: &str) -> Result<String> {
+    let hex: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':')
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::Config(format!(
+            "TLS fingerprint must be 64 hex digits (SHA-256): {}",
+            fingerprint
+        )));
+    }
+    Ok(hex)
+}
+
+fn poisoned() -> io::Error {
+    io::Error::other("TLS stream lock poisoned")
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Reader::Plain(stream) => stream.read(buf),
+            Reader::Tls { stream, socket } => loop {
+                {
+                    // The writer only uses the socket under the lock, so it
+                    // never sees it non-blocking.
+                    let mut stream = stream.lock().map_err(|_| poisoned())?;
+                    stream.get_ref().set_nonblocking(true)?;
+                    let result = stream.read(buf);
+                    stream.get_ref().set_nonblocking(false)?;
+                    match result {
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                        result => return result,
+                    }
+                }
+                // Block until the pool sends more, or the connection is shut
+                // down, without holding the lock.
+                socket.peek(&mut [0; 1])?;
+            },
+        }
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Writer::Plain(stream) => stream.write(buf),
+            Writer::Tls(stream) => stream.lock().map_err(|_| poisoned())?.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Writer::Plain(stream) => stream.flush(),
+            Writer::Tls(stream) => stream.lock().map_err(|_| poisoned())?.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_schemes() {
+        let url = PoolUrl::parse("stratum+ssl://pool.example.com:443").unwrap();
+        assert_eq!(url.scheme, Scheme::Ssl);
+        assert_eq!(url.host, "pool.example.com");
+        assert_eq!(url.port, 443);
+
+        let url = PoolUrl::parse("stratum+tcp://pool.example.com:3333").unwrap();
+        assert_eq!(url.scheme, Scheme::Tcp);
+
+        let url = PoolUrl::parse("pool.example.com:3333").unwrap();
+        assert_eq!(url.scheme, Scheme::Tcp);
+        assert_eq!(url.to_string(), "stratum+tcp://pool.example.com:3333");
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_urls() {
+        assert!(PoolUrl::parse("http://pool.example.com:80").is_err());
+        assert!(PoolUrl::parse("pool.example.com").is_err());
+        assert!(PoolUrl::parse("stratum+tcp://:3333").is_err());
+        assert!(PoolUrl::parse("pool.example.com:port").is_err());
+    }
+
+    #[test]
+    fn test_normalize_fingerprint() {
+        let pin = "AB:cd:0F".to_string() + &":00".repeat(29);
+        assert_eq!(
+            normalize_fingerprint(&pin).unwrap(),
+            "abcd0f".to_string() + &"00".repeat(29)
+        );
+        assert_eq!(
+            normalize_fingerprint(&"a".repeat(64)).unwrap(),
+            "a".repeat(64)
+        );
+        // Too short, a typo that is not hex, and other separators.
+        assert!(normalize_fingerprint("AB:cd:0F").is_err());
+        assert!(normalize_fingerprint(&("g".to_string() + &"a".repeat(63))).is_err());
+        assert!(normalize_fingerprint(&"ab-".repeat(32)).is_err());
+    }
+}
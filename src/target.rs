@@ -0,0 +1,207 @@
+use crate::error::{Error, Result, StratumError};
+use serde::{Deserialize, Deserializer};
+use std::{fmt, str::FromStr};
+
+/// Expected number of hashes per share.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Difficulty(pub u64);
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+// Little-endian 64-bit limbs.
+type U256 = [u64; 4];
+
+const U256_MAX: U256 = [u64::MAX; 4];
+
+/// Largest hash, read as a 256-bit little-endian number, that still makes a
+/// share.
+///
+/// Pools send compact targets of 4 or 8 bytes. Like the pools themselves, a
+/// compact target is read as the difficulty it stands for
+/// (`0xFFFFFFFF / target` or `0xFFFFFFFFFFFFFFFF / target`), and the full
+/// target is `(2^256 - 1) / difficulty`, which is what monerod checks hashes
+/// against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target(U256);
+
+impl Target {
+    pub fn from_difficulty(difficulty: Difficulty) -> Self {
+        Self(div(U256_MAX, [difficulty.0.max(1), 0, 0, 0]))
+    }
+
+    /// Parses a 4-byte or 8-byte compact target or a 32-byte full target, all
+    /// little-endian.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let difficulty = match bytes.len() {
+            4 => u32::MAX as u64 / u32::from_le_bytes(bytes.try_into().unwrap()).max(1) as u64,
+            8 => u64::MAX / u64::from_le_bytes(bytes.try_into().unwrap()).max(1),
+            32 => return Ok(Self(from_le_bytes(bytes))),
+            len => {
+                return Err(StratumError::Protocol(format!(
+                    "Target must be 4, 8 or 32 bytes, got {}",
+                    len
+                ))
+                .into())
+            }
+        };
+        Ok(Self::from_difficulty(Difficulty(difficulty)))
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        match div(U256_MAX, self.0) {
+            [low, 0, 0, 0] => Difficulty(low),
+            _ => Difficulty(u64::MAX),
+        }
+    }
+
+    /// The 4-byte compact form, as sent by Monero pools.
+    pub fn compact(&self) -> [u8; 4] {
+        let difficulty = self.difficulty().0.max(1);
+        ((u32::MAX as u64 / difficulty) as u32).to_le_bytes()
+    }
+
+    /// The 8-byte compact form, which pools switch to for difficulties the
+    /// 4-byte form cannot express.
+    pub fn compact64(&self) -> [u8; 8] {
+        (u64::MAX / self.difficulty().0.max(1)).to_le_bytes()
+    }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.chunks_exact_mut(8).zip(self.0) {
+            chunk.copy_from_slice(&limb.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Whether a 32-byte RandomX hash is at or below the target.
+    pub fn is_met_by(&self, hash: &[u8]) -> bool {
+        cmp(from_le_bytes(hash), self.0).is_le()
+    }
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(hex: &str) -> Result<Self> {
+        Self::from_bytes(&hex::decode(hex)?)
+    }
+}
+
+impl<'de> Deserialize<'de> for Target {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let hex: String = Deserialize::deserialize(deserializer)?;
+        hex.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.to_bytes()))
+    }
+}
+
+fn from_le_bytes(bytes: &[u8]) -> U256 {
+    let mut value = [0u64; 4];
+    for (limb, chunk) in value.iter_mut().zip(bytes.chunks(8)) {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        *limb = u64::from_le_bytes(word);
+    }
+    value
+}
+
+fn cmp(a: U256, b: U256) -> std::cmp::Ordering {
+    a.iter().rev().cmp(b.iter().rev())
+}
+
+// Schoolbook binary long division; only run when a job changes.
+fn div(dividend: U256, divisor: U256) -> U256 {
+    let mut quotient = [0u64; 4];
+    let mut remainder = [0u64; 4];
+    for bit in (0..256).rev() {
+        // remainder = remainder << 1 | next bit of the dividend
+        let overflow = remainder[3] >> 63;
+        for i in (1..4).rev() {
+            remainder[i] = remainder[i] << 1 | remainder[i - 1] >> 63;
+        }
+        remainder[0] = remainder[0] << 1 | (dividend[bit / 64] >> (bit % 64)) & 1;
+        if overflow == 1 || cmp(remainder, divisor).is_ge() {
+            let mut borrow = false;
+            for i in 0..4 {
+                let (diff, b1) = remainder[i].overflowing_sub(divisor[i]);
+                let (diff, b2) = diff.overflowing_sub(borrow as u64);
+                remainder[i] = diff;
+                borrow = b1 || b2;
+            }
+            quotient[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+    quotient
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_targets() {
+        for (hex, difficulty) in [
+            ("37894100", 1000),
+            ("b88d0600", 10000),
+            ("f3220000", 480045),
+            ("ffffff00", 256),
+            ("ffffffffffffff00", 256),
+            ("0000008000000000", 8589934591),
+        ] {
+            let target: Target = hex.parse().unwrap();
+            assert_eq!(target.difficulty(), Difficulty(difficulty), "{}", hex);
+        }
+    }
+
+    #[test]
+    fn test_difficulty_round_trip() {
+        let target = Target::from_difficulty(Difficulty(10000));
+        assert_eq!(hex::encode(target.compact()), "b88d0600");
+        assert_eq!(target.difficulty(), Difficulty(10000));
+        assert_eq!(
+            Target::from_difficulty(Difficulty(1)).to_bytes(),
+            [0xff; 32]
+        );
+        assert_eq!(u64::from_le_bytes(target.compact64()), u64::MAX / 10000);
+
+        let full: Target = hex::encode(target.to_bytes()).parse().unwrap();
+        assert_eq!(full, target);
+    }
+
+    #[test]
+    fn test_hash_against_target() {
+        let target = Target::from_difficulty(Difficulty(256));
+        // (2^256 - 1) / 256 has a zero top byte and ones below it.
+        let mut hash = [0xff; 32];
+        hash[31] = 0x00;
+        assert!(target.is_met_by(&hash));
+        hash[31] = 0x01;
+        hash[0] = 0x00;
+        assert!(!target.is_met_by(&hash));
+
+        // Only the lowest byte differs from the target, which comparing the
+        // top 64 bits alone would miss.
+        let target = Target::from_difficulty(Difficulty(3));
+        let mut hash = target.to_bytes();
+        assert_eq!(hash, [0x55; 32]);
+        assert!(target.is_met_by(&hash));
+        hash[0] = 0x56;
+        assert!(!target.is_met_by(&hash));
+    }
+
+    #[test]
+    fn test_invalid_target() {
+        assert!("b88d06".parse::<Target>().is_err());
+        assert!("zz".parse::<Target>().is_err());
+    }
+}
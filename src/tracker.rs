@@ -0,0 +1,133 @@
+use crate::{job::Job, share::Share};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// Jobs remembered after they were replaced. Shares for older ones are stale
+// under any policy.
+const RECENT_JOBS: usize = 8;
+
+/// What happens to shares found for a job a clean job has replaced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StalePolicy {
+    /// Drop them, as the pool would reject them anyway
+    #[default]
+    Drop,
+    /// Still submit them for a grace window after the job was replaced, for pools that accept them
+    Grace,
+}
+
+/// Where a share's job stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Freshness {
+    Current,
+    /// Replaced, but within the grace window.
+    Late,
+    Stale,
+}
+
+/// Knows the current and recent job ids, and when each was replaced.
+#[derive(Debug)]
+pub struct JobTracker {
+    policy: StalePolicy,
+    grace: Duration,
+    // Oldest first, with when a clean job replaced each.
+    jobs: VecDeque<(String, Option<Instant>)>,
+}
+
+impl JobTracker {
+    /// `grace` only applies under `StalePolicy::Grace`.
+    pub fn new(policy: StalePolicy, grace: Duration) -> Self {
+        Self {
+            policy,
+            grace,
+            jobs: VecDeque::new(),
+        }
+    }
+
+    pub fn set_policy(&mut self, policy: StalePolicy, grace: Duration) {
+        self.policy = policy;
+        self.grace = grace;
+    }
+
+    pub fn update_job(&mut self, job: &Job) {
+        if job.clean {
+            let now = Instant::now();
+            for (_, replaced) in self.jobs.iter_mut() {
+                replaced.get_or_insert(now);
+            }
+        }
+        if self.jobs.len() == RECENT_JOBS {
+            self.jobs.pop_front();
+        }
+        self.jobs.push_back((job.id.clone(), None));
+    }
+
+    pub fn check(&self, share: &Share) -> Freshness {
+        match self.jobs.iter().rev().find(|(id, _)| *id == share.job_id) {
+            Some((_, None)) => Freshness::Current,
+            Some((_, Some(replaced)))
+                if self.policy == StalePolicy::Grace && replaced.elapsed() < self.grace =>
+            {
+                Freshness::Late
+            }
+            _ => Freshness::Stale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{job::test_job, share::test_share};
+    use std::thread;
+
+    fn job(id: &str, clean: bool) -> Job {
+        Job {
+            clean,
+            ..test_job(id, 1)
+        }
+    }
+
+    fn share(job_id: &str) -> Share {
+        test_share(job_id, 0)
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        let mut tracker = JobTracker::new(StalePolicy::Drop, Duration::from_secs(60));
+        tracker.update_job(&job("1", true));
+        assert_eq!(tracker.check(&share("1")), Freshness::Current);
+        // A job that does not clean leaves the previous one current.
+        tracker.update_job(&job("2", false));
+        assert_eq!(tracker.check(&share("1")), Freshness::Current);
+        tracker.update_job(&job("3", true));
+        assert_eq!(tracker.check(&share("1")), Freshness::Stale);
+        assert_eq!(tracker.check(&share("2")), Freshness::Stale);
+        assert_eq!(tracker.check(&share("3")), Freshness::Current);
+        assert_eq!(tracker.check(&share("unknown")), Freshness::Stale);
+    }
+
+    #[test]
+    fn test_grace_policy() {
+        let mut tracker = JobTracker::new(StalePolicy::Grace, Duration::from_millis(50));
+        tracker.update_job(&job("1", true));
+        tracker.update_job(&job("2", true));
+        assert_eq!(tracker.check(&share("1")), Freshness::Late);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(tracker.check(&share("1")), Freshness::Stale);
+        // The window runs from the first clean job after it.
+        tracker.update_job(&job("3", true));
+        assert_eq!(tracker.check(&share("1")), Freshness::Stale);
+        assert_eq!(tracker.check(&share("2")), Freshness::Late);
+
+        for id in 4..4 + RECENT_JOBS {
+            tracker.update_job(&job(&id.to_string(), false));
+        }
+        assert_eq!(tracker.check(&share("3")), Freshness::Stale);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{
+    cpu::{CpuFeatures, CpuOptions},
+    dataset::Dataset,
+    error::Result,
+    event::Event,
+    job::Job,
+    share::Share,
+    stats::Stats,
+};
+
+use randomx_rs::{RandomXFlag, RandomXVM};
+use std::{
+    collections::VecDeque,
+    sync::{
+        mpsc::{self, Sender},
+        Arc,
+    },
+    thread,
+};
+
+// Jobs a share can still be checked against once newer ones arrived.
+const RECENT_JOBS: usize = 4;
+// Checks the invalid rate is taken over, and how many there have to be before
+// it can raise the alert.
+const WINDOW: usize = 100;
+const MIN_CHECKED: usize = 10;
+
+/// Recomputes found shares before they are submitted, so a broken JIT or bad
+/// RAM does not get the miner banned for invalid shares.
+///
+/// Shares are hashed again in light mode with the RandomX interpreter, which
+/// shares no generated code with the worker threads. This happens on a thread
+/// of its own, which sends its verdict on each share to the mining loop as an
+/// `Event::Verified`, so the loop keeps handling events meanwhile. The cache
+/// for a new seed is built as soon as a job with it arrives. Shares whose
+/// hash differs or misses the job's target are counted in `Stats`, and an
+/// error is logged once the share of invalid ones among the last 100 crosses
+/// the alert threshold.
+pub struct Verifier {
+    tasks: Sender<Task>,
+}
+
+enum Task {
+    Job(Job),
+    Share(Share),
+}
+
+impl Verifier {
+    /// Starts verifying with the AES and Argon2 settings in `cpu`, alerting
+    /// when more than `alert_percent` of recent shares are invalid. Verdicts
+    /// are sent to `events`.
+    pub fn new(
+        cpu: &CpuOptions,
+        alert_percent: f64,
+        stats: Arc<Stats>,
+        events: Sender<Event>,
+    ) -> Self {
+        let cpu = cpu.clone();
+        let (tasks, task_rx) = mpsc::channel();
+        // RandomX VMs cannot move between threads, so the checker is created
+        // on the one it runs on. It stops once the `Verifier` is dropped.
+        thread::spawn(move || {
+            let mut checker = Checker::new(&cpu, alert_percent, stats);
+            for task in task_rx {
+                match task {
+                    Task::Job(job) => checker.update_job(job),
+                    Task::Share(share) => {
+                        let valid = checker.check(&share);
+                        if events.send(Event::Verified(share, valid)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+        Self { tasks }
+    }
+
+    /// Makes `job` known, so shares for it can be checked, and builds the
+    /// cache for its seed if that is new.
+    pub fn update_job(&self, job: &Job) {
+        let _ = self.tasks.send(Task::Job(job.clone()));
+    }
+
+    /// Queues `share` to be checked. The verdict follows as an
+    /// `Event::Verified`; shares for jobs the verifier no longer knows, or
+    /// that it cannot hash, pass unchecked.
+    pub fn check(&self, share: Share) {
+        let _ = self.tasks.send(Task::Share(share));
+    }
+}
+
+// The verifier's state, owned by its thread.
+struct Checker {
+    flags: RandomXFlag,
+    alert: f64,
+    stats: Arc<Stats>,
+    jobs: VecDeque<Job>,
+    // The VM and the seed it is keyed with.
+    vm: Option<(Vec<u8>, RandomXVM)>,
+    recent: VecDeque<bool>,
+    alerting: bool,
+}
+
+impl Checker {
+    fn new(cpu: &CpuOptions, alert_percent: f64, stats: Arc<Stats>) -> Self {
+        let options = CpuOptions {
+            no_jit: true,
+            ..cpu.clone()
+        };
+        Self {
+            flags: CpuFeatures::detect().flags(&options),
+            alert: alert_percent / 100.0,
+            stats,
+            jobs: VecDeque::new(),
+            vm: None,
+            recent: VecDeque::new(),
+            alerting: false,
+        }
+    }
+
+    fn update_job(&mut self, job: Job) {
+        if let Err(e) = self.vm(&job.seed) {
+            tracing::warn!("Could not build the verification cache: {}", e);
+        }
+        if self.jobs.len() == RECENT_JOBS {
+            self.jobs.pop_front();
+        }
+        self.jobs.push_back(job);
+    }
+
+    // Returns whether `share` should be submitted.
+    fn check(&mut self, share: &Share) -> bool {
+        let Some(job) = self.jobs.iter().rev().find(|j| j.id == share.job_id) else {
+            tracing::debug!("Not verifying share for unknown job {}", share.job_id);
+            return true;
+        };
+        let job = job.clone();
+        let valid = match self.hash(&job, share) {
+            Ok(Some(hash)) => hash == share.hash && job.target.is_met_by(&hash),
+            Ok(None) => return true,
+            Err(e) => {
+                tracing::warn!("Could not verify share: {}", e);
+                return true;
+            }
+        };
+        if !valid {
+            tracing::warn!(
+                "Dropping share for job {} at nonce {}: hash {} does not verify",
+                share.job_id,
+                hex::encode(&share.nonce),
+                hex::encode(&share.hash)
+            );
+        }
+        self.stats.record_verification(valid);
+        self.record(valid);
+        valid
+    }
+
+    fn hash(&mut self, job: &Job, share: &Share) -> Result<Option<Vec<u8>>> {
+        let Ok(nonce) = <[u8; 4]>::try_from(share.nonce.as_slice()) else {
+            return Ok(None);
+        };
+        let Some(blob) = job.blob_with_nonce(u32::from_le_bytes(nonce)) else {
+            return Ok(None);
+        };
+        Ok(Some(self.vm(&job.seed)?.calculate_hash(&blob)?))
+    }
+
+    // The VM keyed with `seed`, built if the current one is keyed otherwise.
+    fn vm(&mut self, seed: &[u8]) -> Result<&RandomXVM> {
+        if self.vm.as_ref().is_none_or(|(current, _)| current != seed) {
+            // Drop the old VM and cache before building the new ones.
+            self.vm = None;
+            let vm = Dataset::new(self.flags, seed)?.vm()?;
+            self.vm = Some((seed.to_vec(), vm));
+        }
+        Ok(&self.vm.as_ref().expect("VM was just created").1)
+    }
+
+    fn record(&mut self, valid: bool) {
+        if self.recent.len() == WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(valid);
+        if self.recent.len() < MIN_CHECKED {
+            return;
+        }
+        let invalid = self.recent.iter().filter(|valid| !**valid).count();
+        let rate = invalid as f64 / self.recent.len() as f64;
+        if rate > self.alert && !self.alerting {
+            tracing::error!(
+                "{} of the last {} shares failed verification; check the build with \
+                 `selftest` and the RAM, or mine with --no-jit",
+                invalid,
+                self.recent.len()
+            );
+            self.alerting = true;
+        } else if rate <= self.alert && self.alerting {
+            tracing::info!("Invalid share rate back below the alert threshold");
+            self.alerting = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::test_job;
+
+    // The share a worker would find at `nonce`, hashed with the JIT.
+    fn share(job: &Job, nonce: u32) -> Share {
+        let flags = crate::cpu::configure(&CpuOptions::default());
+        let vm = Dataset::new(flags, &job.seed).unwrap().vm().unwrap();
+        job.next_share(&vm, nonce).unwrap()
+    }
+
+    #[test]
+    fn test_drops_and_counts_invalid_shares() {
+        let stats = Stats::new(1);
+        let mut verifier = Checker::new(&CpuOptions::default(), 5.0, stats.clone());
+        let easy = test_job("easy", 1);
+        verifier.update_job(easy.clone());
+        let good = share(&easy, 1);
+        assert!(verifier.check(&good));
+
+        let mut corrupt = good.clone();
+        corrupt.hash[0] ^= 1;
+        assert!(!verifier.check(&corrupt));
+
+        // A genuine hash that misses a harder target.
+        let hard = Share {
+            job_id: "hard".into(),
+            ..good.clone()
+        };
+        verifier.update_job(test_job("hard", u64::MAX));
+        assert!(!verifier.check(&hard));
+
+        // Shares for jobs it never saw pass unchecked.
+        assert!(verifier.check(&Share {
+            job_id: "unknown".into(),
+            ..good
+        }));
+        assert_eq!(
+            stats.verification(),
+            crate::stats::VerifyCounts {
+                checked: 3,
+                invalid: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_alert_threshold() {
+        let stats = Stats::new(1);
+        let mut verifier = Checker::new(&CpuOptions::default(), 10.0, stats);
+        for _ in 0..MIN_CHECKED - 1 {
+            verifier.record(false);
+        }
+        assert!(!verifier.alerting);
+        verifier.record(true);
+        assert!(verifier.alerting);
+        // Back below 10% once 90 more pass.
+        for _ in 0..90 {
+            verifier.record(true);
+        }
+        assert!(!verifier.alerting);
+    }
+}
@@ -1,170 +1,456 @@
+use crate::{
+    cpu::{self, CpuOptions},
+    dataset::{Dataset, SharedDataset},
+    error::Result,
+    event::Event,
+    hugepages,
+    job::Job,
+    share::Share,
+    stats::Stats,
+    tracker::{Freshness, JobTracker, StalePolicy},
+};
 
-use crate::{error::{Error, Result}, job::Job, share::Share};
-
-use core_affinity;
-use randomx_rs::{RandomXCache, RandomXDataset, RandomXFlag, RandomXVM};
+use core_affinity::{self, CoreId};
+use randomx_rs::{RandomXFlag, RandomXVM};
 use std::{
     num::NonZeroUsize,
-    sync::mpsc::{self, Receiver},
-    thread,
-    time::Instant,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        mpsc::Sender,
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+// How long a thread that could not set up a VM waits before trying again.
+const VM_RETRY: Duration = Duration::from_secs(5);
+// Nonces a thread takes from the job's counter at a time.
+const NONCE_BATCH: u32 = 256;
 // Import the specific types from watch crate
-use watch::{channel, WatchSender};
+use watch::{channel, WatchReceiver, WatchSender};
+
+// A job together with the counter its threads draw nonces from, so threads
+// never hash the same nonce however many of them there are.
+#[derive(Clone)]
+struct Work {
+    job: Job,
+    next_nonce: Arc<AtomicU32>,
+}
+
+impl Work {
+    fn new(job: Job) -> Self {
+        Self {
+            job,
+            next_nonce: Arc::default(),
+        }
+    }
+
+    fn next_batch(&self) -> impl Iterator<Item = u32> {
+        let start = self.next_nonce.fetch_add(NONCE_BATCH, Ordering::Relaxed);
+        (0..NONCE_BATCH).map(move |i| start.wrapping_add(i))
+    }
+}
+
+/// What a mining thread reports about itself in an `Event::Thread`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ThreadState {
+    /// Hashing again after a failure.
+    Hashing,
+    /// Could not set up a VM for the current job. The thread hashes nothing
+    /// until a retry succeeds.
+    Failed(String),
+}
+
+// Holds worker threads while mining is paused, without making them give up
+// their VMs.
+#[derive(Default)]
+struct Gate {
+    paused: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl Gate {
+    fn set(&self, paused: bool) {
+        *lock(&self.paused) = paused;
+        self.changed.notify_all();
+    }
+
+    fn is_paused(&self) -> bool {
+        *lock(&self.paused)
+    }
+
+    // Blocks while paused, unless `stop` is raised.
+    fn wait(&self, stop: &AtomicBool) {
+        let mut paused = lock(&self.paused);
+        while *paused && !stop.load(Ordering::Relaxed) {
+            paused = self.changed.wait(paused).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    // Sleeps for `timeout`, unless `stop` is raised first.
+    fn sleep(&self, stop: &AtomicBool, timeout: Duration) {
+        let paused = lock(&self.paused);
+        let _ = self
+            .changed
+            .wait_timeout_while(paused, timeout, |_| !stop.load(Ordering::Relaxed));
+    }
+
+    // Raises `stop` and wakes the thread if it is waiting.
+    fn stop(&self, stop: &AtomicBool) {
+        let _paused = lock(&self.paused);
+        stop.store(true, Ordering::Relaxed);
+        self.changed.notify_all();
+    }
+}
+
 pub struct Worker {
-    share_rx: Receiver<Share>,
-    job_tx: WatchSender<Job>,
+    events: Sender<Event>,
+    job_tx: WatchSender<Work>,
+    datasets: Arc<SharedDataset>,
+    stats: Arc<Stats>,
+    tracker: Mutex<JobTracker>,
+    cores: Vec<CoreId>,
+    gate: Arc<Gate>,
+    // Stop flags and handles of the running threads, by thread index.
+    threads: Mutex<Vec<(Arc<AtomicBool>, JoinHandle<()>)>>,
 }
 impl Worker {
-    #[tracing::instrument(skip(job))]
-    pub fn init(job: Job, num_threads: NonZeroUsize, light: bool) -> Result<Self> {
-        let (share_tx, share_rx) = mpsc::channel();
-        let (job_tx, job_rx) = channel(job.clone());
-
-        let mut flags = RandomXFlag::get_recommended_flags()
-            | RandomXFlag::FLAG_JIT
-            | RandomXFlag::FLAG_HARD_AES;
+    /// Starts `num_threads` threads mining `job`. Shares they find are sent
+    /// to `events` as `Event::Share`, and threads that cannot set up a VM
+    /// report it as an `Event::Thread`.
+    #[tracing::instrument(skip(job, events))]
+    pub fn init(
+        job: Job,
+        num_threads: NonZeroUsize,
+        light: bool,
+        huge_pages: bool,
+        cpu: CpuOptions,
+        stats: Arc<Stats>,
+        events: Sender<Event>,
+    ) -> Result<Self> {
+        let (job_tx, _) = channel(Work::new(job.clone()));
+
+        let mut flags = cpu::configure(&cpu);
         if !light {
             flags |= RandomXFlag::FLAG_FULL_MEM;
         }
+        if huge_pages {
+            flags |= RandomXFlag::FLAG_LARGE_PAGES;
+            hugepages::report(num_threads.get(), !light);
+        }
+
+        stats.record_job(&job);
+        let mut tracker = JobTracker::new(StalePolicy::Drop, Duration::ZERO);
+        tracker.update_job(&job);
+        let datasets = Arc::new(SharedDataset::new(
+            flags,
+            &job.seed,
+            num_threads,
+            stats.clone(),
+        )?);
+        if let Some(next_seed) = job.next_seed.as_ref().filter(|s| **s != job.seed) {
+            datasets.prepare(next_seed);
+        }
+        let worker = Self {
+            events,
+            job_tx,
+            datasets,
+            stats,
+            tracker: Mutex::new(tracker),
+            cores: match cpu.affinity {
+                Some(ids) => ids.into_iter().map(|id| CoreId { id }).collect(),
+                None => core_affinity::get_core_ids().unwrap_or_default(),
+            },
+            gate: Arc::default(),
+            threads: Mutex::new(Vec::new()),
+        };
+        worker.set_threads(num_threads);
+
+        let reporter = Arc::downgrade(&worker.stats);
+        thread::spawn(move || loop {
+            thread::sleep(REPORT_INTERVAL);
+            let Some(stats) = reporter.upgrade() else {
+                return;
+            };
+            tracing::info!(
+                "Total Hashrate: {:.2} H/s",
+                stats.hashrate()[0].unwrap_or_default()
+            );
+        });
+        Ok(worker)
+    }
+    /// Whether `share` is still worth submitting under the stale policy.
+    /// Those that are not are counted as dropped.
+    pub fn accept_share(&self, share: &Share) -> bool {
+        match lock(&self.tracker).check(share) {
+            Freshness::Current => true,
+            Freshness::Late => {
+                tracing::debug!("Submitting late share for replaced job {}", share.job_id);
+                true
+            }
+            Freshness::Stale => {
+                tracing::debug!("Dropping stale share for job {}", share.job_id);
+                self.stats.record_stale_dropped();
+                false
+            }
+        }
+    }
+    /// Sets what happens to shares found for jobs that were replaced. By
+    /// default they are dropped.
+    pub fn set_stale_policy(&self, policy: StalePolicy, grace: Duration) {
+        lock(&self.tracker).set_policy(policy, grace);
+    }
+    pub fn update_job(&self, job: Job) {
+        if let Some(next_seed) = job.next_seed.as_ref().filter(|s| **s != job.seed) {
+            self.datasets.prepare(next_seed);
+        }
+        self.stats.record_job(&job);
+        lock(&self.tracker).update_job(&job);
+        self.job_tx.send(Work::new(job));
+    }
+    /// The dataset for the current job, e.g. to hash outside the worker
+    /// threads with `Dataset::vm`.
+    pub fn dataset(&self) -> Result<Arc<Dataset>> {
+        let seed = self.job_tx.subscribe().get().job.seed;
+        self.datasets.get(&seed)
+    }
+    /// Stops hashing until `resume`. Threads keep their VMs meanwhile.
+    pub fn pause(&self) {
+        self.gate.set(true);
+    }
+    pub fn resume(&self) {
+        self.gate.set(false);
+    }
+    pub fn is_paused(&self) -> bool {
+        self.gate.is_paused()
+    }
+    pub fn threads(&self) -> usize {
+        lock(&self.threads).len()
+    }
+    /// Starts or stops threads until `count` are running. Stopped threads
+    /// finish the hash they are on and free their VM.
+    pub fn set_threads(&self, count: NonZeroUsize) {
+        let mut threads = lock(&self.threads);
+        while threads.len() > count.get() {
+            if let Some((stop, _)) = threads.pop() {
+                self.gate.stop(&stop);
+            }
+        }
+        while threads.len() < count.get() {
+            let thread = self.spawn(threads.len());
+            threads.push(thread);
+        }
+        self.stats.set_threads(threads.len());
+        self.datasets.set_init_threads(count);
+    }
+    /// Stops all threads and waits for them to finish their current hash.
+    /// Every share they found has been sent by the time it returns.
+    pub fn stop(&self) {
+        let threads: Vec<_> = lock(&self.threads).drain(..).collect();
+        for (stop, _) in &threads {
+            self.gate.stop(stop);
+        }
+        for (_, handle) in threads {
+            let _ = handle.join();
+        }
+        self.stats.set_threads(0);
+    }
+    fn spawn(&self, i: usize) -> (Arc<AtomicBool>, JoinHandle<()>) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let core_id = self.cores.get(i % self.cores.len().max(1)).cloned();
+        let thread = Thread {
+            index: i,
+            stop: stop.clone(),
+            gate: self.gate.clone(),
+            events: self.events.clone(),
+            job_rx: self.job_tx.subscribe(),
+            datasets: self.datasets.clone(),
+            stats: self.stats.clone(),
+        };
+        let handle = thread::spawn(move || {
+            if let Some(core) = core_id {
+                core_affinity::set_for_current(core);
+                tracing::info!("Thread {i} pinned to core {:?}", core.id);
+            }
+            thread.run();
+            tracing::debug!("Thread {i} stopped");
+        });
+        (stop, handle)
+    }
+}
 
-        let cores = core_affinity::get_core_ids().unwrap_or_default();
-        let (hashrate_tx, hashrate_rx) = mpsc::channel();
-        for i in 0..num_threads.get() {
-            let core_id = cores.get(i % cores.len()).cloned();
-            let share_tx = share_tx.clone();
-            let mut job_rx = job_rx.clone();
-            let hashrate_tx = hashrate_tx.clone();
-            let thread_nonce_start = (i as u32) * (u32::MAX / num_threads.get() as u32);
-            let thread_nonce_end = thread_nonce_start + (u32::MAX / num_threads.get() as u32);
-            thread::spawn(move || {
-                if let Some(core) = core_id {
-                    core_affinity::set_for_current(core);
-                    tracing::info!("Thread {i} pinned to core {:?}", core.id);
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// State one hashing thread runs on.
+struct Thread {
+    index: usize,
+    stop: Arc<AtomicBool>,
+    gate: Arc<Gate>,
+    events: Sender<Event>,
+    job_rx: WatchReceiver<Work>,
+    datasets: Arc<SharedDataset>,
+    stats: Arc<Stats>,
+}
+
+impl Thread {
+    fn run(mut self) {
+        let i = self.index;
+        let hashes = self.stats.hash_counter(i);
+        let mut work = self.job_rx.get();
+        let Some(mut vm) = self.vm(&mut work) else {
+            return;
+        };
+        let mut batch = work.next_batch();
+        let mut accepted = 0;
+        let mut last_report = Instant::now();
+
+        tracing::debug!(
+            "Thread {i} starting with difficulty: {}",
+            work.job.difficulty()
+        );
+        while !self.stop.load(Ordering::Relaxed) {
+            self.gate.wait(&self.stop);
+            if let Some(mut new_work) = self.job_rx.get_if_new() {
+                if new_work.job.seed != work.job.seed {
+                    // Hashing on with the old VM would only find stale shares.
+                    let Some(new_vm) = self.vm(&mut new_work) else {
+                        break;
+                    };
+                    vm = new_vm;
+                    tracing::debug!("Thread {i} switched to dataset for new job seed");
                 }
-                let mut cache = match RandomXCache::new(flags, &job_rx.get().seed) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        tracing::error!("randomx cache error: {}", e);
-                        return;
-                    }
-                };
-                let mut dataset = if flags.contains(RandomXFlag::FLAG_FULL_MEM) {
-                    match RandomXDataset::new(flags, cache.clone(), 0) {
-                        Ok(d) => Some(d),
-                        Err(e) => {
-                            tracing::error!("dataset error: {}", e);
-                            return;
-                        }
-                    }
-                } else {
-                    None
-                };
-                let mut vm = match RandomXVM::new(flags, Some(cache.clone()), dataset.clone()) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        tracing::error!("vm init error: {}", e);
-                        return;
-                    }
-                };
-                let mut job = job_rx.get().clone();
-                let mut target = job.difficulty();
-                let mut nonce = thread_nonce_start;
-                let mut accepted = 0;
-                let mut hashes = 0;
-                let mut last_report = Instant::now();
-
-                tracing::debug!(
-                    "Thread {i} starting with target: {}, nonce range: {}-{}",
-                    target,
-                    thread_nonce_start,
-                    thread_nonce_end
+                work = new_work;
+                batch = work.next_batch();
+                accepted = 0;
+                last_report = Instant::now();
+            }
+            let Some(nonce) = batch.next() else {
+                batch = work.next_batch();
+                continue;
+            };
+            hashes.fetch_add(1, Ordering::Relaxed);
+            if let Some(share) = work.job.next_share(&vm, nonce) {
+                accepted += 1;
+                tracing::debug!("Found share at nonce: {}", nonce);
+                if self.events.send(Event::Share(share)).is_err() {
+                    break;
+                }
+            }
+            if last_report.elapsed() >= REPORT_INTERVAL {
+                tracing::info!(
+                    "Thread {i} - Hashrate: {:.2} H/s, Shares: {}",
+                    self.stats.thread_hashrate(i)[0].unwrap_or_default(),
+                    accepted
                 );
-                loop {
-                    if let Some(new_job) = job_rx.get_if_new() {
-                        if new_job.seed != job.seed {
-                            cache = match RandomXCache::new(flags, &new_job.seed) {
-                                Ok(c) => c,
-                                Err(e) => {
-                                    tracing::error!("cache init error: {}", e);
-                                    continue;
-                                }
-                            };
-                            vm.reinit_cache(cache.clone()).ok();
-                            if flags.contains(RandomXFlag::FLAG_FULL_MEM) {
-                                dataset = match RandomXDataset::new(flags, cache.clone(), 0) {
-                                    Ok(d) => Some(d),
-                                    Err(e) => {
-                                        tracing::error!("dataset init error: {}", e);
-                                        continue;
-                                    }
-                                };
-                                if let Some(ref d) = dataset {
-                                    vm.reinit_dataset(d.clone()).ok();
-                                }
-                            }
-                            tracing::debug!("Thread {i} reinitialized context with new job seed");
-                        }
-                        job = new_job;
-                        target = job.difficulty();
-                        nonce = thread_nonce_start;
-                        accepted = 0;
-                        last_report = Instant::now();
-                    }
-                    // Inside the worker thread loop:
-                    if nonce <= thread_nonce_end {
-                        hashes += 1;
-                        if let Some(share) = job.next_share(&vm, nonce, target) {
-                            accepted += 1;
-                            tracing::debug!("Found share at nonce: {}", nonce);
-                            if share_tx.send(share).is_err() {
-                                break;
-                            }
-                        }
-                        nonce += 1;
-                    } else {
-                        nonce = thread_nonce_start;
-                    }
-                    if last_report.elapsed().as_secs() >= 10 {
-                        let hashrate = hashes as f64 / 10.0;
-                        if let Err(e) = hashrate_tx.send(hashrate) {
-                            tracing::warn!("Failed to send hashrate: {}", e);
-                        }
-                        tracing::info!(
-                            "Thread {i} - Hashrate: {:.2} H/s, Shares: {}",
-                            hashrate,
-                            accepted
-                        );
-                        hashes = 0;
-                        last_report = Instant::now();
+                last_report = Instant::now();
+            }
+        }
+    }
+
+    // Sets up a VM for `work`. While that fails the thread reports itself
+    // failed and retries, moving on to newer work if any arrives. Returns
+    // `None` once it is told to stop.
+    fn vm(&mut self, work: &mut Work) -> Option<RandomXVM> {
+        let i = self.index;
+        let mut failed = false;
+        loop {
+            match self.datasets.get(&work.job.seed).and_then(|d| d.vm()) {
+                Ok(vm) => {
+                    if failed {
+                        tracing::info!("Thread {i} is hashing again");
+                        let _ = self.events.send(Event::Thread(i, ThreadState::Hashing));
                     }
+                    return Some(vm);
                 }
-            });
-        }
-        // Spawn hashrate aggregator thread
-        thread::spawn(move || {
-            let mut last_report = Instant::now();
-            let mut total_hashrate = 0.0;
-            let mut count = 0;
-            loop {
-                if let Ok(hashrate) = hashrate_rx.recv_timeout(std::time::Duration::from_secs(10)) {
-                    total_hashrate += hashrate;
-                    count += 1;
-                    if count >= num_threads.get() || last_report.elapsed().as_secs() >= 10 {
-                        tracing::info!("Total Hashrate: {:.2} H/s", total_hashrate);
-                        total_hashrate = 0.0;
-                        count = 0;
-                        last_report = Instant::now();
+                Err(e) => {
+                    tracing::error!(
+                        "Thread {i} could not set up a VM, retrying in {}s: {}",
+                        VM_RETRY.as_secs(),
+                        e
+                    );
+                    if !failed {
+                        failed = true;
+                        let state = ThreadState::Failed(e.to_string());
+                        let _ = self.events.send(Event::Thread(i, state));
                     }
                 }
             }
-        });
-        Ok(Self { share_rx, job_tx })
+            self.gate.sleep(&self.stop, VM_RETRY);
+            if self.stop.load(Ordering::Relaxed) {
+                return None;
+            }
+            if let Some(newer) = self.job_rx.get_if_new() {
+                *work = newer;
+            }
+        }
     }
-    pub fn try_recv_share(&self) -> Option<Share> {
-        self.share_rx.try_recv().ok()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::test_job;
+
+    #[test]
+    fn test_work_batches_do_not_overlap() {
+        let work = Work::new(test_job("a", 1));
+        let other = work.clone();
+        let first: Vec<u32> = work.next_batch().collect();
+        let second: Vec<u32> = other.next_batch().collect();
+        assert_eq!(first.len(), NONCE_BATCH as usize);
+        assert_eq!(first[0], 0);
+        assert_eq!(second[0], NONCE_BATCH);
+        // A new job starts from the beginning again.
+        assert_eq!(Work::new(work.job.clone()).next_batch().next(), Some(0));
     }
-    pub fn update_job(&self, job: Job) {
-        let _ = self.job_tx.send(job);
+
+    #[test]
+    fn test_drops_shares_for_replaced_jobs() {
+        // Every hash is a share.
+        let job = |id: &str| test_job(id, 1);
+        let stats = Stats::new(1);
+        let (events, event_rx) = crate::event::channel();
+        let worker = Worker::init(
+            job("old"),
+            NonZeroUsize::new(1).unwrap(),
+            true,
+            false,
+            CpuOptions::default(),
+            stats.clone(),
+            events,
+        )
+        .unwrap();
+        // Let shares for the first job pile up, then replace it.
+        thread::sleep(Duration::from_secs(1));
+        worker.update_job(job("new"));
+        let mut accepted = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while stats.stale_dropped() == 0 {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match event_rx.recv_timeout(wait) {
+                Ok(Event::Share(share)) => {
+                    accepted.extend(worker.accept_share(&share).then_some(share))
+                }
+                Ok(_) => {}
+                Err(e) => panic!("no share for the replaced job was dropped: {}", e),
+            }
+        }
+        worker.stop();
+        for event in event_rx.try_iter() {
+            if let Event::Share(share) = event {
+                accepted.extend(worker.accept_share(&share).then_some(share));
+            }
+        }
+        assert!(accepted.iter().all(|share| share.job_id == "new"));
     }
 }